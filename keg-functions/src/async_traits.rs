@@ -0,0 +1,487 @@
+use async_trait::async_trait;
+use chrono::Local;
+use std::time::{Duration, Instant};
+use crate::statements::{split_statements, Dialect};
+use crate::traits::{
+    assert_schema_history_queries, baseline_query, delete_history_query, execution_time_query, history_batch,
+    history_query, info_migrations, legacy_checksum_upgrades, may_be_partially_applied, migration_batch_error,
+    pending_migrations, pending_repeatable_migrations, pending_undo_migrations, plan_migrations, repair_queries,
+    repeatable_history_queries, schema_history_state, HistoryQuery, SchemaHistory, SchemaHistoryState, verify_migrations,
+    verify_no_failed_migrations, verify_grouped_migrations, verify_pending_migrations, APPLIED_MIGRATIONS_QUERY,
+    APPLIED_REPEATABLE_MIGRATIONS_QUERY,
+};
+use crate::{
+    AppliedMigration, Error, Migration, MigrationInfo, MigrationReport, Param, Plan, Report, Runner, SchemaColumn,
+    Version, WrapMigrationError,
+};
+
+// the statements of a migration followed by the queries updating the schema history
+struct MigrationBatch<'m> {
+    migration: &'m Migration,
+    statements: Vec<&'m str>,
+    history_queries: Vec<HistoryQuery>,
+}
+
+impl<'m> MigrationBatch<'m> {
+    fn new(migration: &'m Migration, dialect: Dialect, history_queries: Vec<HistoryQuery>) -> MigrationBatch<'m> {
+        MigrationBatch {
+            migration,
+            statements: split_statements(&migration.sql, dialect),
+            history_queries,
+        }
+    }
+
+    fn queries(&self) -> impl Iterator<Item = (&str, &[Param])> {
+        self.statements
+            .iter()
+            .map(|statement| (*statement, &[] as &[Param]))
+            .chain(history_batch(&self.history_queries))
+    }
+
+    fn len(&self) -> usize {
+        self.statements.len() + self.history_queries.len()
+    }
+
+    fn error<E>(&self, index: usize, err: E) -> Error
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        migration_batch_error(self.migration, &self.statements, self.history_queries.len(), index, err)
+    }
+}
+
+// maps the index of the failed query of batches executed together to the migration it belongs to
+fn grouped_batches_error<E>(batches: &[MigrationBatch], mut index: usize, err: E) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    for batch in batches.iter() {
+        if index < batch.len() {
+            return batch.error(index, err);
+        }
+        index -= batch.len();
+    }
+    Error::TransactionError("error applying migrations".into(), Box::new(err))
+}
+
+// executes a batch on its own, the history is only updated once every statement of a migration
+// that can't run in a transaction succeeded
+async fn execute_migration_batch<C>(conn: &mut C, batch: &MigrationBatch<'_>) -> Result<Vec<usize>, (usize, C::Error)>
+where
+    C: AsyncTransaction + ?Sized,
+{
+    if batch.migration.no_transaction() {
+        let rows_affected = conn.execute_no_transaction(&batch.statements).await?;
+        conn.execute(&history_batch(&batch.history_queries))
+            .await
+            .map(|_| rows_affected)
+            .map_err(|(index, err)| (batch.statements.len() + index, err))
+    } else {
+        conn.execute(&batch.queries().collect::<Vec<_>>()).await
+    }
+}
+
+// like the sync plan and info, a missing table has nothing applied and one of a previous keg version
+// is only read once a run upgraded it
+async fn get_schema_history<C>(conn: &mut C) -> Result<Option<SchemaHistory>, Error>
+where
+    C: AsyncMigrate + ?Sized,
+{
+    let upgrade_columns = conn
+        .get_schema_history_columns("keg_schema_history_upgrade")
+        .await
+        .migration_err("error getting migrations table columns")?;
+    let columns = conn
+        .get_schema_history_columns("keg_schema_history")
+        .await
+        .migration_err("error getting migrations table columns")?;
+    match schema_history_state(C::DIALECT, &upgrade_columns, &columns) {
+        SchemaHistoryState::NeedsUpgrade => Ok(None),
+        SchemaHistoryState::Missing => Ok(Some((Vec::new(), Vec::new()))),
+        SchemaHistoryState::UpToDate => {
+            let applied = conn
+                .get_applied_migrations()
+                .await
+                .migration_err("error getting applied migrations")?;
+            let applied_repeatable = conn
+                .get_applied_repeatable_migrations()
+                .await
+                .migration_err("error getting applied repeatable migrations")?;
+            Ok(Some((applied, applied_repeatable)))
+        }
+    }
+}
+
+async fn upgrade_legacy_checksums<C>(
+    conn: &mut C,
+    mut applied: Vec<AppliedMigration>,
+    migrations: &[Migration],
+) -> Result<Vec<AppliedMigration>, Error>
+where
+    C: AsyncTransaction + ?Sized,
+{
+    for (migration, query) in legacy_checksum_upgrades(&mut applied, migrations) {
+        conn.execute(&history_batch(&[query]))
+            .await
+            .map_err(|(_, err)| err)
+            .migration_err(&format!("error upgrading checksum of migration {}", migration))?;
+    }
+    Ok(applied)
+}
+
+#[async_trait]
+pub trait AsyncTransaction: Send {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    // dialect used to split migrations into statements
+    const DIALECT: Dialect = Dialect::Generic;
+
+    // executes the queries binding their params to the `?` placeholders in a single transaction and
+    // returns the rows affected by each and how long it took, errors come with the index of the failed query,
+    // or queries.len() if starting or committing the transaction failed
+    async fn execute_timed(
+        &mut self,
+        queries: &[(&str, &[Param])],
+    ) -> Result<Vec<(usize, Duration)>, (usize, Self::Error)>;
+
+    // like execute_timed, returning only the rows affected by each query
+    async fn execute(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let executed = self.execute_timed(queries).await?;
+        Ok(executed.into_iter().map(|(rows_affected, _)| rows_affected).collect())
+    }
+
+    // like execute but each query is committed on its own, for migrations which can't run in a transaction
+    async fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)>;
+}
+
+#[async_trait]
+pub trait AsyncLock: AsyncTransaction {
+    // takes the lock keeping concurrent runs from migrating, waiting up to timeout for it to be released,
+    // returns false if it's still held by then
+    async fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error>;
+
+    async fn unlock(&mut self) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+pub trait AsyncQuery<T>: AsyncTransaction {
+    async fn query(&mut self, query: &str) -> Result<Vec<T>, Self::Error>;
+}
+
+#[async_trait]
+pub trait AsyncMigrate: AsyncQuery<AppliedMigration> + Send {
+    // columns of the given schema history table, empty if it doesn't exist
+    async fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error>;
+
+    // runs the queries in a single execute, so that an upgrade is never left half done on backends
+    // with transactional DDL
+    async fn assert_schema_history_table(&mut self) -> Result<usize, Self::Error> {
+        let upgrade_columns = self.get_schema_history_columns("keg_schema_history_upgrade").await?;
+        let columns = self.get_schema_history_columns("keg_schema_history").await?;
+        let queries = assert_schema_history_queries(Self::DIALECT, &upgrade_columns, &columns);
+        let queries = queries
+            .iter()
+            .map(|query| (query.as_str(), &[] as &[Param]))
+            .collect::<Vec<_>>();
+        let rows_affected = self.execute(&queries).await.map_err(|(_, err)| err)?;
+        Ok(rows_affected.iter().sum())
+    }
+
+    // versions are stored as text, so they are sorted here instead of with ORDER BY
+    async fn get_applied_migrations(&mut self) -> Result<Vec<AppliedMigration>, Self::Error> {
+        let mut applied = self.query(APPLIED_MIGRATIONS_QUERY).await?;
+        applied.sort_by(|a, b| a.version.cmp(&b.version));
+        Ok(applied)
+    }
+
+    async fn get_applied_repeatable_migrations(&mut self) -> Result<Vec<AppliedMigration>, Self::Error> {
+        self.query(APPLIED_REPEATABLE_MIGRATIONS_QUERY).await
+    }
+
+    // when grouped every migration is applied in a single transaction, each reporting
+    // the time its own statements took
+    async fn migrate(
+        &mut self,
+        migrations: &[Migration],
+        grouped: bool,
+        out_of_order: bool,
+        target: Option<&Version>,
+    ) -> Result<Report, Error> {
+        self.assert_schema_history_table()
+            .await
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .await
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(self, applied, migrations).await?;
+        let applied_repeatable = self
+            .get_applied_repeatable_migrations()
+            .await
+            .migration_err("error getting applied repeatable migrations")?;
+        // failed attempts come first, as fixing the sql of a failed migration changes its checksum
+        verify_no_failed_migrations(applied.iter().chain(&applied_repeatable))?;
+        verify_migrations(&applied, migrations)?;
+        let pending = pending_migrations(&applied, migrations, target);
+        verify_pending_migrations(&applied, &pending, out_of_order, target)?;
+        let repeatable = pending_repeatable_migrations(&applied_repeatable, migrations, target);
+
+        if pending.is_empty() && repeatable.is_empty() {
+            log::debug!("no migrations to apply");
+        }
+
+        // the migration statements followed by the queries updating the schema history,
+        // their execution time is recorded once they are committed
+        let mut batches = Vec::new();
+        for migration in pending.iter() {
+            let history_queries = vec![history_query(Self::DIALECT, migration, true, None)];
+            batches.push(MigrationBatch::new(migration, Self::DIALECT, history_queries));
+        }
+        for migration in repeatable.iter() {
+            let history_queries = repeatable_history_queries(Self::DIALECT, migration, None);
+            batches.push(MigrationBatch::new(migration, Self::DIALECT, history_queries));
+        }
+
+        let mut report = Report::default();
+        if grouped {
+            verify_grouped_migrations(batches.iter().map(|batch| batch.migration))?;
+            let queries = batches.iter().flat_map(MigrationBatch::queries).collect::<Vec<_>>();
+            let started_on = Local::now();
+            let executed = self
+                .execute_timed(&queries)
+                .await
+                .map_err(|(index, err)| grouped_batches_error(&batches, index, err))?;
+            // each migration starts once the queries of the ones before it are done
+            let mut elapsed = Duration::default();
+            let mut execution_time_queries = Vec::new();
+            let mut offset = 0;
+            for batch in batches.iter() {
+                let executed = &executed[offset..offset + batch.len()];
+                let statements = &executed[..batch.statements.len()];
+                let duration = statements.iter().map(|(_, duration)| *duration).sum();
+                let rows_affected = statements.iter().map(|(rows_affected, _)| rows_affected).sum();
+                let migration_started_on =
+                    started_on + chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero());
+                report.push(MigrationReport::new(batch.migration, migration_started_on, duration, rows_affected));
+                execution_time_queries.push(execution_time_query(batch.migration, duration));
+
+                elapsed += executed.iter().map(|(_, duration)| *duration).sum::<Duration>();
+                offset += batch.len();
+            }
+            if let Err((_, err)) = self.execute(&history_batch(&execution_time_queries)).await {
+                log::warn!("error recording execution time of migrations: {}", err);
+            }
+        } else {
+            for batch in batches.iter() {
+                log::debug!("applying migration: {}", batch.migration);
+                let started_on = Local::now();
+                let start = Instant::now();
+                let rows_affected = match execute_migration_batch(self, batch).await {
+                    Ok(rows_affected) => rows_affected,
+                    Err((index, err)) => {
+                        // failing to record the failed attempt is only logged as the migration error is the one to report
+                        if may_be_partially_applied(Self::DIALECT, batch.migration) {
+                            let failed_query = [history_query(Self::DIALECT, batch.migration, false, None)];
+                            if let Err((_, err)) = self.execute(&history_batch(&failed_query)).await {
+                                log::warn!("error recording failed migration {}: {}", batch.migration, err);
+                            }
+                        }
+                        return Err(Error::RunAborted(Box::new(batch.error(index, err)), report));
+                    }
+                };
+                let duration = start.elapsed();
+                let execution_time_query = [execution_time_query(batch.migration, duration)];
+                if let Err((_, err)) = self.execute(&history_batch(&execution_time_query)).await {
+                    log::warn!("error recording execution time of migration {}: {}", batch.migration, err);
+                }
+                let rows_affected = rows_affected[..batch.statements.len()].iter().sum();
+                report.push(MigrationReport::new(batch.migration, started_on, duration, rows_affected));
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn plan(
+        &mut self,
+        migrations: &[Migration],
+        grouped: bool,
+        out_of_order: bool,
+        target: Option<&Version>,
+    ) -> Result<Plan, Error> {
+        match get_schema_history(self).await? {
+            Some((applied, applied_repeatable)) => Ok(plan_migrations(
+                applied,
+                &applied_repeatable,
+                migrations,
+                grouped,
+                out_of_order,
+                target,
+            )),
+            None => Ok(Plan {
+                pending: Vec::new(),
+                problems: vec![Error::SchemaHistoryNeedsUpgrade],
+            }),
+        }
+    }
+
+    async fn info(&mut self, migrations: &[Migration]) -> Result<Vec<MigrationInfo>, Error> {
+        match get_schema_history(self).await? {
+            Some((applied, applied_repeatable)) => Ok(info_migrations(applied, &applied_repeatable, migrations)),
+            None => Err(Error::SchemaHistoryNeedsUpgrade),
+        }
+    }
+
+    async fn baseline(&mut self, version: &Version, description: &str) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .await
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .await
+            .migration_err("error getting applied migrations")?;
+        let applied_repeatable = self
+            .get_applied_repeatable_migrations()
+            .await
+            .migration_err("error getting applied repeatable migrations")?;
+        if !applied.is_empty() || !applied_repeatable.is_empty() {
+            return Err(Error::SchemaHistoryNotEmpty);
+        }
+
+        log::debug!("baselining schema history at version: {}", version);
+        let query = [baseline_query(Self::DIALECT, version, description)];
+        self.execute(&history_batch(&query))
+            .await
+            .map_err(|(_, err)| err)
+            .migration_err("error updating schema history to baseline")?;
+
+        Ok(())
+    }
+
+    async fn repair(&mut self, migrations: &[Migration]) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .await
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .await
+            .migration_err("error getting applied migrations")?;
+        let queries = repair_queries(&applied, migrations);
+        self.execute(&history_batch(&queries))
+            .await
+            .map_err(|(_, err)| err)
+            .migration_err("error repairing schema history")?;
+
+        Ok(())
+    }
+
+    // when grouped every undo migration is applied in a single transaction
+    async fn rollback(&mut self, migrations: &[Migration], grouped: bool, target: &Version) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .await
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .await
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(self, applied, migrations).await?;
+        verify_no_failed_migrations(&applied)?;
+        verify_migrations(&applied, migrations)?;
+
+        let batches = pending_undo_migrations(&applied, migrations, target)?
+            .into_iter()
+            .map(|(applied, migration)| {
+                MigrationBatch::new(migration, Self::DIALECT, vec![delete_history_query(&applied.version)])
+            })
+            .collect::<Vec<_>>();
+        if grouped {
+            verify_grouped_migrations(batches.iter().map(|batch| batch.migration))?;
+            let queries = batches.iter().flat_map(MigrationBatch::queries).collect::<Vec<_>>();
+            self.execute(&queries)
+                .await
+                .map_err(|(index, err)| grouped_batches_error(&batches, index, err))?;
+        } else {
+            for batch in batches.iter() {
+                log::debug!("reverting migration: {}", batch.migration.name);
+                execute_migration_batch(self, batch)
+                    .await
+                    .map_err(|(index, err)| batch.error(index, err))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Runner {
+    /// Async counterpart of `run`, applying the pending migrations with the same ordering,
+    /// history, grouping and migrations lock
+    pub async fn run_async<C>(&self, conn: &mut C) -> Result<Report, Error> where C: AsyncMigrate + AsyncLock {
+        self.lock_async(conn).await?;
+        let result = conn.migrate(&self.migrations, self.grouped, self.out_of_order, self.target.as_ref()).await;
+        unlock_async(conn, result).await
+    }
+
+    /// Async counterpart of `plan`
+    pub async fn plan_async<C>(&self, conn: &mut C) -> Result<Plan, Error> where C: AsyncMigrate {
+        conn.plan(&self.migrations, self.grouped, self.out_of_order, self.target.as_ref()).await
+    }
+
+    /// Async counterpart of `info`
+    pub async fn info_async<C>(&self, conn: &mut C) -> Result<Vec<MigrationInfo>, Error> where C: AsyncMigrate {
+        conn.info(&self.migrations).await
+    }
+
+    /// Async counterpart of `baseline`
+    pub async fn baseline_async<C>(&self, conn: &mut C, version: Version, description: &str) -> Result<(), Error>
+    where
+        C: AsyncMigrate + AsyncLock,
+    {
+        self.lock_async(conn).await?;
+        let result = conn.baseline(&version, description).await;
+        unlock_async(conn, result).await
+    }
+
+    /// Async counterpart of `repair`
+    pub async fn repair_async<C>(&self, conn: &mut C) -> Result<(), Error> where C: AsyncMigrate + AsyncLock {
+        self.lock_async(conn).await?;
+        let result = conn.repair(&self.migrations).await;
+        unlock_async(conn, result).await
+    }
+
+    /// Async counterpart of `rollback_to`
+    pub async fn rollback_to_async<C>(&self, conn: &mut C, version: Version) -> Result<(), Error>
+    where
+        C: AsyncMigrate + AsyncLock,
+    {
+        self.lock_async(conn).await?;
+        let result = conn.rollback(&self.migrations, self.grouped, &version).await;
+        unlock_async(conn, result).await
+    }
+
+    async fn lock_async<C>(&self, conn: &mut C) -> Result<(), Error> where C: AsyncLock {
+        match conn.lock(self.lock_timeout).await {
+            Ok(true) => {
+                log::debug!("acquired migrations lock");
+                Ok(())
+            }
+            Ok(false) => Err(Error::LockTimeout(self.lock_timeout)),
+            Err(err) => Err(Error::LockError(Box::new(err))),
+        }
+    }
+}
+
+// releases the lock taken by lock_async even if the locked operation failed, whose error comes first
+async fn unlock_async<C, T>(conn: &mut C, result: Result<T, Error>) -> Result<T, Error>
+where
+    C: AsyncLock,
+{
+    let unlocked = conn.unlock().await;
+    let value = result?;
+    unlocked.map_err(|err| Error::LockError(Box::new(err)))?;
+    Ok(value)
+}
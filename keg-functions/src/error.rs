@@ -1,10 +1,28 @@
+use crate::{AppliedMigration, Migration, Report, Version};
 use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Error {
     InvalidName,
     InvalidVersion,
+    InvalidMigrationPath(PathBuf, io::Error),
+    InvalidMigrationFile(PathBuf, Box<Error>),
+    DuplicateMigrationVersion(PathBuf, PathBuf),
     TransactionError(String, Box<dyn std::error::Error + Sync + Send>),
+    DivergentVersions(Vec<(AppliedMigration, Migration)>),
+    MissingUndoMigration(Box<AppliedMigration>),
+    OutOfOrderMigrations(Vec<Migration>),
+    SchemaHistoryNotEmpty,
+    SchemaHistoryNeedsUpgrade,
+    TargetVersionBelowCurrent(Version, Version),
+    RunAborted(Box<Error>, Report),
+    FailedMigrations(Vec<AppliedMigration>),
+    LockTimeout(Duration),
+    LockError(Box<dyn std::error::Error + Sync + Send>),
+    NoTransactionMigrationsGrouped(Vec<Migration>),
 }
 
 impl fmt::Display for Error {
@@ -12,14 +30,97 @@ impl fmt::Display for Error {
         match self {
             Error::InvalidName => write!(
                 fmt,
-                "migration name must be in the format V{{number}}__{{name}}"
+                "migration name must be in the format V{{number}}__{{name}}, U{{number}}__{{name}} or R__{{name}}"
             )?,
             Error::InvalidVersion => {
-                write!(fmt, "migration version must be a valid integer")?
+                write!(fmt, "migration version must be dot separated numbers, such as 1, 1.2.3 or 20261017093000")?
             }
+            Error::InvalidMigrationPath(path, cause) => {
+                write!(fmt, "could not read migrations from {}, {}", path.display(), cause)?
+            }
+            Error::InvalidMigrationFile(path, cause) => {
+                write!(fmt, "invalid migration file {}, {}", path.display(), cause)?
+            }
+            Error::DuplicateMigrationVersion(path, other) => write!(
+                fmt,
+                "duplicate migration version found in {} and {}",
+                path.display(),
+                other.display()
+            )?,
             Error::TransactionError(msg, cause) => {
                 write!(fmt, "error applying migration {}, {}", msg, cause)?
             }
+            Error::DivergentVersions(divergent) => {
+                write!(fmt, "applied migrations differ from the local ones:")?;
+                for (applied, migration) in divergent.iter() {
+                    write!(
+                        fmt,
+                        " applied V{}__{} with checksum {} but found {} with checksum {};",
+                        applied.version,
+                        applied.name,
+                        applied.checksum,
+                        migration,
+                        migration.checksum()
+                    )?;
+                }
+            }
+            Error::MissingUndoMigration(applied) => write!(
+                fmt,
+                "no undo migration found for applied migration V{}__{}",
+                applied.version, applied.name
+            )?,
+            Error::OutOfOrderMigrations(migrations) => {
+                write!(
+                    fmt,
+                    "found pending migrations with a version lower than the current one, enable out of order to apply them:"
+                )?;
+                for migration in migrations.iter() {
+                    write!(fmt, " {}", migration)?;
+                }
+            }
+            Error::SchemaHistoryNotEmpty => write!(
+                fmt,
+                "cannot baseline a database which already has applied migrations"
+            )?,
+            Error::SchemaHistoryNeedsUpgrade => write!(
+                fmt,
+                "the schema history table was created by a previous keg version and is upgraded on the next run"
+            )?,
+            Error::TargetVersionBelowCurrent(target, current) => write!(
+                fmt,
+                "target version {} is lower than the current version {}",
+                target, current
+            )?,
+            Error::FailedMigrations(failed) => {
+                write!(
+                    fmt,
+                    "found failed migrations, repair the schema history before migrating:"
+                )?;
+                for applied in failed.iter() {
+                    write!(fmt, " {}", applied)?;
+                }
+            }
+            Error::LockTimeout(timeout) => write!(
+                fmt,
+                "timed out after {:?} waiting for another run to release the migrations lock",
+                timeout
+            )?,
+            Error::LockError(cause) => write!(fmt, "error taking the migrations lock, {}", cause)?,
+            Error::NoTransactionMigrationsGrouped(migrations) => {
+                write!(
+                    fmt,
+                    "found pending migrations which can't run in a transaction, disable grouped to apply them:"
+                )?;
+                for migration in migrations.iter() {
+                    write!(fmt, " {}", migration)?;
+                }
+            }
+            Error::RunAborted(cause, report) => write!(
+                fmt,
+                "{}, after applying {} migrations",
+                cause,
+                report.applied().len()
+            )?,
         }
         Ok(())
     }
@@ -28,7 +129,11 @@ impl fmt::Display for Error {
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
+            Error::InvalidMigrationPath(_path, cause) => Some(cause),
+            Error::InvalidMigrationFile(_path, cause) => Some(&**cause),
             Error::TransactionError(_migration, cause) => Some(&**cause),
+            Error::RunAborted(cause, _report) => Some(&**cause),
+            Error::LockError(cause) => Some(&**cause),
             _ => None,
         }
     }
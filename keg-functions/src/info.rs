@@ -0,0 +1,165 @@
+use crate::{AppliedMigration, Migration, Version};
+
+/// State of a migration when comparing the schema history with the local migrations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    /// Applied and matching the local migration
+    Applied,
+    /// Not applied yet, or for repeatable migrations changed since last applied
+    Pending,
+    /// Applied but not found locally
+    MissingLocally,
+    /// Applied with a different name or checksum than the local migration
+    ChecksumMismatch,
+    /// Applied with a version higher than the latest local migration
+    Future,
+    /// Last attempt to apply it failed, needs to be repaired
+    Failed,
+}
+
+/// Migration as found locally and in the schema history
+#[derive(Clone, Debug)]
+pub struct MigrationInfo {
+    state: MigrationState,
+    migration: Option<Migration>,
+    applied: Option<AppliedMigration>,
+}
+
+impl MigrationInfo {
+    pub fn state(&self) -> MigrationState {
+        self.state
+    }
+
+    /// The local migration, if any
+    pub fn migration(&self) -> Option<&Migration> {
+        self.migration.as_ref()
+    }
+
+    /// The schema history row, if any
+    pub fn applied(&self) -> Option<&AppliedMigration> {
+        self.applied.as_ref()
+    }
+
+    fn version(&self) -> Option<&Version> {
+        self.applied
+            .as_ref()
+            .map(|a| &a.version)
+            .or_else(|| self.migration.as_ref().map(|m| &m.version))
+    }
+}
+
+// merges the schema history with the local migrations, versioned migrations first in version order
+// and then repeatable ones by name. local migrations up to the baseline are considered applied
+pub(crate) fn migrations_info(
+    applied: &[AppliedMigration],
+    applied_repeatable: &[AppliedMigration],
+    migrations: &[Migration],
+) -> Vec<MigrationInfo> {
+    let latest = migrations.iter().filter(|m| m.is_versioned()).map(|m| &m.version).max();
+    let baseline = applied.iter().filter(|a| a.is_baseline()).map(|a| &a.version).max();
+
+    let mut versioned = Vec::new();
+    for applied in applied.iter() {
+        let migration = migrations
+            .iter()
+            .find(|m| m.is_versioned() && m.version == applied.version);
+        let state = match migration {
+            _ if !applied.success => MigrationState::Failed,
+            _ if applied.is_baseline() => MigrationState::Applied,
+            Some(migration) if migration.name != applied.name || migration.checksum().to_string() != applied.checksum => {
+                MigrationState::ChecksumMismatch
+            }
+            Some(_) => MigrationState::Applied,
+            None => match latest {
+                Some(latest) if applied.version < *latest => MigrationState::MissingLocally,
+                _ => MigrationState::Future,
+            },
+        };
+        versioned.push(MigrationInfo {
+            state,
+            migration: migration.cloned(),
+            applied: Some(applied.clone()),
+        });
+    }
+
+    for migration in migrations.iter().filter(|m| m.is_versioned()) {
+        let below_baseline = match baseline {
+            Some(baseline) => migration.version <= *baseline,
+            None => false,
+        };
+        if !below_baseline && !applied.iter().any(|a| a.version == migration.version) {
+            versioned.push(MigrationInfo {
+                state: MigrationState::Pending,
+                migration: Some(migration.clone()),
+                applied: None,
+            });
+        }
+    }
+    versioned.sort_by(|a, b| a.version().cmp(&b.version()));
+
+    let mut repeatable = Vec::new();
+    for migration in migrations.iter().filter(|m| m.is_repeatable()) {
+        let applied = applied_repeatable
+            .iter()
+            .filter(|a| a.name == migration.name)
+            .max_by_key(|a| !a.success);
+        let state = match applied {
+            Some(applied) if !applied.success => MigrationState::Failed,
+            Some(applied) if applied.checksum == migration.checksum().to_string() => MigrationState::Applied,
+            _ => MigrationState::Pending,
+        };
+        repeatable.push(MigrationInfo {
+            state,
+            migration: Some(migration.clone()),
+            applied: applied.cloned(),
+        });
+    }
+    for applied in applied_repeatable.iter() {
+        if applied.success && !migrations.iter().any(|m| m.is_repeatable() && m.name == applied.name) {
+            repeatable.push(MigrationInfo {
+                state: MigrationState::MissingLocally,
+                migration: None,
+                applied: Some(applied.clone()),
+            });
+        }
+    }
+    repeatable.sort_by(|a, b| {
+        let name = |info: &MigrationInfo| {
+            info.applied
+                .as_ref()
+                .map(|a| a.name.clone())
+                .or_else(|| info.migration.as_ref().map(|m| m.name.clone()))
+        };
+        name(a).cmp(&name(b))
+    });
+
+    versioned.into_iter().chain(repeatable).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrations_info, MigrationState};
+    use crate::{AppliedMigration, AppliedMigrationType, Migration};
+    use chrono::Local;
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            name: migration.name.clone(),
+            version: migration.version.clone(),
+            migration_type: AppliedMigrationType::of(migration),
+            description: None,
+            installed_on: Some(Local::now()),
+            checksum: migration.checksum().to_string(),
+            success: true,
+        }
+    }
+
+    #[test]
+    fn reports_applied_migrations_above_latest_local_as_future() {
+        let first = Migration::from_filename("V1__first", "SELECT 1;").unwrap();
+        let second = Migration::from_filename("V2__second", "SELECT 2;").unwrap();
+        let info = migrations_info(&[applied(&first), applied(&second)], &[], &[first]);
+        let states = info.iter().map(|i| i.state()).collect::<Vec<_>>();
+        assert_eq!(vec![MigrationState::Applied, MigrationState::Future], states);
+    }
+}
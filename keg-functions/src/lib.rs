@@ -1,15 +1,32 @@
+#[cfg(feature = "async")]
+mod async_traits;
 mod error;
+mod info;
+mod report;
+mod statements;
 mod traits;
+mod util;
+mod version;
 
 use regex::Regex;
 use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use chrono::{DateTime, Local};
+use crc::crc32;
 
 pub use error::{Error, WrapMigrationError};
-pub use traits::{Transaction, DefaultQueries, CommitTransaction, ExecuteMultiple, Query, Migrate, MigrateGrouped};
+pub use info::{MigrationInfo, MigrationState};
+pub use report::{MigrationReport, Report};
+pub use statements::{split_statements, Dialect};
+pub use util::{find_migration_files, load_sql_migrations, parse_migration_files, rerun_if_migrations_changed, MigrationType};
+pub use version::Version;
+pub use traits::{Param, Transaction, DefaultQueries, CommitTransaction, ExecuteMultiple, Lock, Query, Migrate, MigrateGrouped};
+#[cfg(feature = "async")]
+pub use async_traits::{AsyncTransaction, AsyncQuery, AsyncMigrate, AsyncLock};
 
 #[cfg(feature = "rusqlite")]
 pub mod rusqlite;
@@ -20,62 +37,202 @@ pub mod postgres;
 #[cfg(feature = "mysql")]
 pub mod mysql;
 
+#[cfg(all(feature = "async", feature = "tokio-postgres"))]
+pub mod tokio_postgres;
+
+#[cfg(all(feature = "async", feature = "mysql_async"))]
+pub mod mysql_async;
+
+/// Matches a whole migration file stem, the version is optional so that repeatable migrations match
 pub fn file_match_re() -> Regex {
-    Regex::new(r"([V])([\d|\.]+)__(\w+)").unwrap()
+    Regex::new(r"^([VRU])(\d[\d.]*)?__(\w+)$").unwrap()
 }
 
 lazy_static::lazy_static! {
     static ref RE: regex::Regex = file_match_re();
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum MigrationPrefix {
     Versioned,
+    Repeatable,
+    Undo,
 }
+
+/// A migration, versioned migrations are applied once in version order,
+/// repeatable migrations have no version and are applied after the versioned ones
+/// every time their checksum changes, undo migrations revert the versioned migration
+/// of the same version when rolling back
 #[derive(Clone, Debug)]
 pub struct Migration {
     name: String,
-    version: usize,
+    version: Version,
     prefix: MigrationPrefix,
     sql: String,
+    no_transaction: bool,
+    migration_type: MigrationType,
+    description: Option<String>,
 }
 
 impl Migration {
     pub fn from_filename(name: &str, sql: &str) -> Result<Migration, Error> {
-        let captures = RE
-            .captures(name)
-            .filter(|caps| caps.len() == 4)
-            .ok_or(Error::InvalidName)?;
-        let version = captures[2]
-            .parse()
-            .map_err(|_| Error::InvalidVersion)?;
-
-        let name = (&captures[3]).into();
+        let captures = RE.captures(name).ok_or(Error::InvalidName)?;
+        let version = captures.get(2).map(|version| version.as_str());
         let prefix = match &captures[1] {
             "V" => MigrationPrefix::Versioned,
+            "R" => MigrationPrefix::Repeatable,
+            "U" => MigrationPrefix::Undo,
             _ => unreachable!(),
         };
 
+        let version = match prefix {
+            MigrationPrefix::Versioned | MigrationPrefix::Undo => version
+                .ok_or(Error::InvalidName)?
+                .parse()
+                .map_err(|_| Error::InvalidVersion)?,
+            // repeatable migrations don't have a version
+            MigrationPrefix::Repeatable if version.is_none() => Version::default(),
+            MigrationPrefix::Repeatable => return Err(Error::InvalidName),
+        };
+
+        let name = (&captures[3]).into();
+
         Ok(Migration {
             name,
             version,
             sql: sql.into(),
-            prefix
+            prefix,
+            no_transaction: has_no_transaction_directive(sql),
+            migration_type: MigrationType::Sql,
+            description: description_directive(sql),
         })
     }
 
-    pub fn checksum(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.name.hash(&mut hasher);
-        self.version.hash(&mut hasher);
-        self.sql.hash(&mut hasher);
-        hasher.finish()
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the migration, repeatable migrations don't have one
+    pub fn version(&self) -> Option<&Version> {
+        if self.is_repeatable() {
+            None
+        } else {
+            Some(&self.version)
+        }
+    }
+
+    pub fn is_versioned(&self) -> bool {
+        self.prefix == MigrationPrefix::Versioned
+    }
+
+    pub fn is_repeatable(&self) -> bool {
+        self.prefix == MigrationPrefix::Repeatable
+    }
+
+    pub fn is_undo(&self) -> bool {
+        self.prefix == MigrationPrefix::Undo
+    }
+
+    /// Whether the migration runs outside of a transaction, for statements such as
+    /// `CREATE INDEX CONCURRENTLY` which can't run in one. Set by a `-- keg:no-transaction`
+    /// comment at the top of the sql
+    pub fn no_transaction(&self) -> bool {
+        self.no_transaction
+    }
+
+    /// Used for mod migrations, which opt out by declaring `pub const NO_TRANSACTION: bool = true`,
+    /// the undo migration of their `down` function opts out with `pub const DOWN_NO_TRANSACTION: bool = true`
+    pub fn set_no_transaction(&mut self, no_transaction: bool) {
+        self.no_transaction = no_transaction;
+    }
+
+    /// Whether the migration is a sql file or a Rust module, recorded in the schema history
+    pub fn migration_type(&self) -> MigrationType {
+        self.migration_type
+    }
+
+    /// Used for mod migrations, which are created from the sql they return
+    pub fn set_migration_type(&mut self, migration_type: MigrationType) {
+        self.migration_type = migration_type;
+    }
+
+    /// Free-form description recorded in the schema history. Set by a `-- keg:description <text>`
+    /// comment at the top of the sql, mod migrations put it at the top of the sql they return
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn set_description(&mut self, description: &str) {
+        self.description = Some(description.to_string());
+    }
+
+    /// CRC32 of the migration sql, normalized so that line endings and trailing whitespace
+    /// don't change the checksum
+    pub fn checksum(&self) -> u32 {
+        crc32::checksum_ieee(normalize_sql(&self.sql).as_bytes())
+    }
+
+    // checksum stored by previous keg versions, it relies on DefaultHasher which is not
+    // guaranteed to be stable across Rust releases, it's only used to upgrade existing history rows.
+    // previous versions only supported usize versions
+    pub(crate) fn legacy_checksum(&self) -> Option<u64> {
+        match self.version.components() {
+            [version] => {
+                let version = usize::try_from(*version).ok()?;
+                let mut hasher = DefaultHasher::new();
+                self.name.hash(&mut hasher);
+                version.hash(&mut hasher);
+                self.sql.hash(&mut hasher);
+                Some(hasher.finish())
+            }
+            _ => None,
+        }
     }
 }
 
+// looks for the directive in the comments before the first statement
+fn has_no_transaction_directive(sql: &str) -> bool {
+    sql.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take_while(|line| line.starts_with("--"))
+        .any(|line| line[2..].trim().eq_ignore_ascii_case("keg:no-transaction"))
+}
+
+// looks for the directive in the comments before the first statement, the rest of its line is the description
+fn description_directive(sql: &str) -> Option<String> {
+    sql.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take_while(|line| line.starts_with("--"))
+        .filter_map(|line| {
+            let comment = line[2..].trim_start();
+            match comment.get(..15) {
+                Some(directive) if directive.eq_ignore_ascii_case("keg:description") => {
+                    Some(comment[15..].trim().to_string())
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn normalize_sql(sql: &str) -> String {
+    sql.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
 impl fmt::Display for Migration {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(fmt, "V{}__{}", self.version, self.name)
+        match self.prefix {
+            MigrationPrefix::Versioned => write!(fmt, "V{}__{}", self.version, self.name),
+            MigrationPrefix::Repeatable => write!(fmt, "R__{}", self.name),
+            MigrationPrefix::Undo => write!(fmt, "U{}__{}", self.version, self.name),
+        }
     }
 }
 
@@ -83,13 +240,16 @@ impl Eq for Migration {}
 
 impl Ord for Migration {
     fn cmp(&self, other: &Migration) -> Ordering {
-        self.version.cmp(&other.version)
+        self.prefix.cmp(&other.prefix).then_with(|| match self.prefix {
+            MigrationPrefix::Repeatable => self.name.cmp(&other.name),
+            _ => self.version.cmp(&other.version),
+        })
     }
 }
 
 impl PartialEq for Migration {
     fn eq(&self, other: &Migration) -> bool {
-        self.version == other.version
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -99,16 +259,154 @@ impl PartialOrd for Migration {
     }
 }
 
+/// Column of keg_schema_history as described by the database,
+/// used to upgrade history tables created by previous keg versions
 #[derive(Debug)]
+pub struct SchemaColumn {
+    name: String,
+    data_type: String,
+    nullable: bool,
+}
+
+/// What a schema history row records, read back from its type column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppliedMigrationType {
+    Sql,
+    Mod,
+    RepeatableSql,
+    RepeatableMod,
+    Baseline,
+}
+
+impl AppliedMigrationType {
+    pub(crate) fn of(migration: &Migration) -> AppliedMigrationType {
+        match (migration.migration_type(), migration.is_repeatable()) {
+            (MigrationType::Sql, false) => AppliedMigrationType::Sql,
+            (MigrationType::Mod, false) => AppliedMigrationType::Mod,
+            (MigrationType::Sql, true) => AppliedMigrationType::RepeatableSql,
+            (MigrationType::Mod, true) => AppliedMigrationType::RepeatableMod,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            AppliedMigrationType::Sql => "SQL",
+            AppliedMigrationType::Mod => "RUST",
+            AppliedMigrationType::RepeatableSql => "SQL_REPEATABLE",
+            AppliedMigrationType::RepeatableMod => "RUST_REPEATABLE",
+            AppliedMigrationType::Baseline => "BASELINE",
+        }
+    }
+
+    // rows copied from the tables of previous keg versions, which didn't record the type, are read
+    // as sql migrations, repeatable ones were recorded without version
+    pub(crate) fn from_column(column: Option<String>, version: &Version) -> AppliedMigrationType {
+        match column.unwrap_or_default().as_str() {
+            "SQL" => AppliedMigrationType::Sql,
+            "RUST" => AppliedMigrationType::Mod,
+            "SQL_REPEATABLE" => AppliedMigrationType::RepeatableSql,
+            "RUST_REPEATABLE" => AppliedMigrationType::RepeatableMod,
+            "BASELINE" => AppliedMigrationType::Baseline,
+            _ if version.components().is_empty() => AppliedMigrationType::RepeatableSql,
+            _ => AppliedMigrationType::Sql,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct AppliedMigration {
     name: String,
-    version: usize,
-    installed_on: DateTime<Local>,
+    version: Version,
+    migration_type: AppliedMigrationType,
+    description: Option<String>,
+    installed_on: Option<DateTime<Local>>,
     checksum: String,
+    success: bool,
+}
+
+impl AppliedMigration {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the migration, repeatable migrations are recorded without one
+    pub fn version(&self) -> Option<&Version> {
+        if self.is_repeatable() {
+            None
+        } else {
+            Some(&self.version)
+        }
+    }
+
+    pub fn migration_type(&self) -> AppliedMigrationType {
+        self.migration_type
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_ref().map(AsRef::as_ref)
+    }
+
+    /// When the migration was applied, `None` for rows whose timestamp was lost
+    /// upgrading the schema history table of a previous keg version
+    pub fn installed_on(&self) -> Option<DateTime<Local>> {
+        self.installed_on
+    }
+
+    /// Checksum of the migration when it was applied, empty for baseline rows
+    pub fn checksum(&self) -> &str {
+        &self.checksum
+    }
+
+    /// Whether the migration was applied, failed attempts are recorded until repaired
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    // baseline rows mark the version an existing database was adopted at,
+    // they don't correspond to any migration so they have no checksum
+    pub(crate) fn is_baseline(&self) -> bool {
+        self.migration_type == AppliedMigrationType::Baseline
+    }
+
+    pub(crate) fn is_repeatable(&self) -> bool {
+        self.migration_type == AppliedMigrationType::RepeatableSql
+            || self.migration_type == AppliedMigrationType::RepeatableMod
+    }
+}
+
+/// Migrations a run would apply, in the order they would be applied,
+/// and the problems that would make it fail
+#[derive(Debug)]
+pub struct Plan {
+    pending: Vec<Migration>,
+    problems: Vec<Error>,
+}
+
+impl Plan {
+    pub fn pending(&self) -> &[Migration] {
+        &self.pending
+    }
+
+    pub fn problems(&self) -> &[Error] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for AppliedMigration {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version() {
+            Some(version) => write!(fmt, "V{}__{}", version, self.name),
+            None => write!(fmt, "R__{}", self.name),
+        }
+    }
 }
 
 pub struct Runner {
     grouped: bool,
+    out_of_order: bool,
+    target: Option<Version>,
+    lock_timeout: Duration,
+    stale_lock_timeout: Option<Duration>,
     migrations: Vec<Migration>
 }
 
@@ -116,6 +414,10 @@ impl Runner {
     pub fn new(migrations: &[Migration]) -> Runner {
         Runner {
             grouped: false,
+            out_of_order: false,
+            target: None,
+            lock_timeout: Duration::from_secs(30),
+            stale_lock_timeout: None,
             migrations: migrations.to_vec()
         }
     }
@@ -123,12 +425,203 @@ impl Runner {
         self.grouped = grouped;
     }
 
-    pub fn run<'a, C>(&self, conn: &'a mut C) -> Result<(), Error> where C: MigrateGrouped<'a> + Migrate {
-        if self.grouped {
-            MigrateGrouped::migrate(conn, &self.migrations)?;
-        } else {
-            Migrate::migrate(conn, &self.migrations)?;
+    /// Apply pending migrations with a version lower than the current one instead of failing
+    pub fn set_out_of_order(&mut self, out_of_order: bool) {
+        self.out_of_order = out_of_order;
+    }
+
+    /// Stop after applying the migration with the given version instead of migrating to the latest one,
+    /// running fails if the target is lower than the current version. Repeatable migrations are skipped
+    /// until the target reaches the latest version
+    pub fn set_target(&mut self, target: Version) {
+        self.target = Some(target);
+    }
+
+    /// How long to wait for concurrent runs to release the migrations lock, 30 seconds by default
+    pub fn set_lock_timeout(&mut self, lock_timeout: Duration) {
+        self.lock_timeout = lock_timeout;
+    }
+
+    /// How old the migrations lock of a run that didn't finish has to be for another run to take it over.
+    /// Only SQLite needs it, its lock is a row that outlives the connection, the other backends release
+    /// the lock with the session. It must be longer than the longest run, as a live run's lock is taken over
+    /// just the same, by default a lock is never taken over and has to be released with `Lock::unlock`
+    pub fn set_stale_lock_timeout(&mut self, stale_lock_timeout: Duration) {
+        self.stale_lock_timeout = Some(stale_lock_timeout);
+    }
+
+    /// Applies the pending migrations and reports the ones applied, when not grouped
+    /// a failure is returned as `Error::RunAborted` with the migrations applied before it
+    pub fn run<C>(&self, conn: &mut C) -> Result<Report, Error> where C: for<'a> MigrateGrouped<'a> + Migrate + Lock {
+        self.with_lock(conn, |conn| {
+            if self.grouped {
+                MigrateGrouped::migrate(conn, &self.migrations, self.out_of_order, self.target.as_ref())
+            } else {
+                Migrate::migrate(conn, &self.migrations, self.out_of_order, self.target.as_ref())
+            }
+        })
+    }
+
+    /// Lists the migrations `run` would apply without applying them, nothing is written to the database,
+    /// without a schema history table every migration is pending. A schema history table of a previous keg version is only upgraded by `run`, until then nothing
+    /// is listed and the plan reports `Error::SchemaHistoryNeedsUpgrade`.
+    /// When set to grouped, pending no-transaction migrations are reported as `Error::NoTransactionMigrationsGrouped`
+    pub fn plan<'a, C>(&self, conn: &'a mut C) -> Result<Plan, Error> where C: MigrateGrouped<'a> {
+        conn.plan(&self.migrations, self.grouped, self.out_of_order, self.target.as_ref())
+    }
+
+    /// Lists the applied and local migrations with their state, applied versioned migrations
+    /// first in version order followed by the repeatable ones. Fails with `Error::SchemaHistoryNeedsUpgrade`
+    /// until `run` upgrades a schema history table of a previous keg version
+    pub fn info<'a, C>(&self, conn: &'a mut C) -> Result<Vec<MigrationInfo>, Error> where C: MigrateGrouped<'a> {
+        conn.info(&self.migrations)
+    }
+
+    /// Records `version` as already applied on a database whose schema was created outside keg,
+    /// without executing any migration, so that only migrations above it are applied afterwards.
+    /// Fails if the schema history already has rows
+    pub fn baseline<C>(&self, conn: &mut C, version: Version, description: &str) -> Result<(), Error> where C: for<'a> MigrateGrouped<'a> + Migrate + Lock {
+        self.with_lock(conn, |conn| {
+            if self.grouped {
+                MigrateGrouped::baseline(conn, &version, description)
+            } else {
+                Migrate::baseline(conn, &version, description)
+            }
+        })
+    }
+
+    /// Deletes the failed migrations recorded in the schema history and aligns the name and checksum
+    /// of the applied migrations with the local ones, the schema itself must be fixed beforehand
+    pub fn repair<C>(&self, conn: &mut C) -> Result<(), Error> where C: for<'a> MigrateGrouped<'a> + Migrate + Lock {
+        self.with_lock(conn, |conn| {
+            if self.grouped {
+                MigrateGrouped::repair(conn, &self.migrations)
+            } else {
+                Migrate::repair(conn, &self.migrations)
+            }
+        })
+    }
+
+    /// Reverts the applied migrations above `version` in reverse order using their undo migrations
+    pub fn rollback_to<C>(&self, conn: &mut C, version: Version) -> Result<(), Error> where C: for<'a> MigrateGrouped<'a> + Migrate + Lock {
+        self.with_lock(conn, |conn| {
+            if self.grouped {
+                MigrateGrouped::rollback(conn, &self.migrations, &version)
+            } else {
+                Migrate::rollback(conn, &self.migrations, &version)
+            }
+        })
+    }
+
+    // runs f holding the migrations lock, which is released even if f fails
+    fn with_lock<C, T, F>(&self, conn: &mut C, f: F) -> Result<T, Error>
+    where
+        C: Lock,
+        F: FnOnce(&mut C) -> Result<T, Error>,
+    {
+        let locked = match self.stale_lock_timeout {
+            Some(stale_lock_timeout) => conn.lock_taking_over_stale(self.lock_timeout, stale_lock_timeout),
+            None => conn.lock(self.lock_timeout),
+        };
+        match locked {
+            Ok(true) => log::debug!("acquired migrations lock"),
+            Ok(false) => return Err(Error::LockTimeout(self.lock_timeout)),
+            Err(err) => return Err(Error::LockError(Box::new(err))),
         }
-        Ok(())
+        let result = f(conn);
+        let unlocked = conn.unlock();
+        let value = result?;
+        unlocked.map_err(|err| Error::LockError(Box::new(err)))?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Migration;
+
+    #[test]
+    fn parses_repeatable_migration() {
+        let migration = Migration::from_filename("R__refresh_views", "SELECT 1;").unwrap();
+        assert!(migration.is_repeatable());
+        assert_eq!("R__refresh_views", migration.to_string());
+    }
+
+    #[test]
+    fn rejects_repeatable_migration_with_version() {
+        assert!(Migration::from_filename("R1__refresh_views", "SELECT 1;").is_err());
+    }
+
+    #[test]
+    fn rejects_partially_matching_names() {
+        assert!(Migration::from_filename("V__first", "SELECT 1;").is_err());
+        assert!(Migration::from_filename("V1__add-cars", "SELECT 1;").is_err());
+        assert!(Migration::from_filename("old_V1__first", "SELECT 1;").is_err());
+    }
+
+    #[test]
+    fn sorts_repeatable_migrations_after_versioned() {
+        let mut migrations = [
+            Migration::from_filename("R__b", "").unwrap(),
+            Migration::from_filename("V2__second", "").unwrap(),
+            Migration::from_filename("R__a", "").unwrap(),
+            Migration::from_filename("V1__first", "").unwrap(),
+        ];
+        migrations.sort();
+        let names = migrations.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(vec!["V1__first", "V2__second", "R__a", "R__b"], names);
+    }
+
+    #[test]
+    fn parses_undo_migration() {
+        let migration = Migration::from_filename("U3__add_brand_to_cars_table", "SELECT 1;").unwrap();
+        assert!(migration.is_undo());
+        assert_eq!("U3__add_brand_to_cars_table", migration.to_string());
+    }
+
+    #[test]
+    fn parses_timestamp_version() {
+        let migration = Migration::from_filename("V20261017093000__add_cars_table", "SELECT 1;").unwrap();
+        assert_eq!("V20261017093000__add_cars_table", migration.to_string());
+        let previous = Migration::from_filename("V2__add_persons_table", "SELECT 1;").unwrap();
+        assert!(previous < migration);
+    }
+
+    #[test]
+    fn parses_no_transaction_directive() {
+        let sql = "-- adds an index\n-- keg:no-transaction\nCREATE INDEX CONCURRENTLY cars_name ON cars(name);";
+        assert!(Migration::from_filename("V1__add_cars_index", sql).unwrap().no_transaction());
+        let sql = "CREATE INDEX cars_name ON cars(name);\n-- keg:no-transaction";
+        assert!(!Migration::from_filename("V1__add_cars_index", sql).unwrap().no_transaction());
+    }
+
+    #[test]
+    fn parses_description_directive() {
+        let sql = "-- keg:no-transaction\n-- KEG:description Adds the cars index \nCREATE INDEX cars_name ON cars(name);";
+        let migration = Migration::from_filename("V1__add_cars_index", sql).unwrap();
+        assert_eq!(Some("Adds the cars index"), migration.description());
+        let sql = "CREATE INDEX cars_name ON cars(name);\n-- keg:description Adds the cars index";
+        assert_eq!(None, Migration::from_filename("V1__add_cars_index", sql).unwrap().description());
+    }
+
+    #[test]
+    fn parses_dotted_version() {
+        let migration = Migration::from_filename("V1.2.10__add_cars_table", "SELECT 1;").unwrap();
+        assert_eq!("V1.2.10__add_cars_table", migration.to_string());
+        let previous = Migration::from_filename("V1.2.9__add_persons_table", "SELECT 1;").unwrap();
+        assert!(previous < migration);
+    }
+
+    #[test]
+    fn checksum_is_crc32_of_sql() {
+        let migration = Migration::from_filename("V1__first", "SELECT 1;").unwrap();
+        assert_eq!(78787420, migration.checksum());
+    }
+
+    #[test]
+    fn checksum_ignores_line_endings_and_trailing_whitespace() {
+        let migration = Migration::from_filename("V1__first", "SELECT 1;").unwrap();
+        let edited = Migration::from_filename("V1__first", "SELECT 1;  \r\n\r\n").unwrap();
+        assert_eq!(migration.checksum(), edited.checksum());
     }
 }
@@ -1,40 +1,121 @@
 use crate::{
     CommitTransaction, DefaultQueries, Migrate, MigrateGrouped, Error,
-    AppliedMigration, Query, Transaction, WrapMigrationError, ExecuteMultiple
+    AppliedMigration, AppliedMigrationType, SchemaColumn, Lock, Query, Transaction, WrapMigrationError, ExecuteMultiple, Dialect, Param
 };
-use chrono::{DateTime, Local};
+use crate::traits::{parse_applied_version, parse_installed_on};
+use chrono::{Datelike, Timelike};
 use mysql::{
-    error::Error as MError, params::Params, Conn, IsolationLevel, PooledConn, Transaction as MTransaction,
+    error::Error as MError, params::Params, prelude::FromValue, Conn, FromValueError, IsolationLevel, PooledConn, Row,
+    Transaction as MTransaction, Value,
 };
+use std::time::Duration;
 
-fn query_migration_version(transaction: &mut MTransaction, query: &str) -> Result<Option<AppliedMigration>, MError> {
+const LOCK_NAME: &str = "keg_schema_history";
+
+// GET_LOCK waits the given number of seconds, 1 is returned when the lock is taken
+fn lock_query(timeout: Duration) -> String {
+    let seconds = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+    format!("SELECT GET_LOCK('{}', {})", LOCK_NAME, seconds)
+}
+
+fn to_values(params: &[Param]) -> Vec<Value> {
+    params
+        .iter()
+        .map(|param| match param {
+            Param::Text(text) => text.as_ref().map_or(Value::NULL, |text| Value::Bytes(text.clone().into_bytes())),
+            Param::Int(value) => value.map_or(Value::NULL, Value::Int),
+            Param::Bool(value) => Value::Int(*value as i64),
+            Param::Timestamp(value) => Value::Date(
+                value.year() as u16,
+                value.month() as u8,
+                value.day() as u8,
+                value.hour() as u8,
+                value.minute() as u8,
+                value.second() as u8,
+                value.nanosecond() / 1000,
+            ),
+        })
+        .collect()
+}
+
+// runs the query with the text protocol, as prepared statements don't support every statement
+fn execute_query(transaction: &mut MTransaction, query: &str) -> Result<usize, MError> {
+    let result = MTransaction::query(transaction, query)?;
+    Ok(result.affected_rows() as usize)
+}
+
+// history queries with params are prepared to bind them, migration statements run as text
+fn execute_params(transaction: &mut MTransaction, query: &str, params: &[Param]) -> Result<usize, MError> {
+    if params.is_empty() {
+        return execute_query(transaction, query);
+    }
+    let result = transaction.prep_exec(query, Params::Positional(to_values(params)))?;
+    Ok(result.affected_rows() as usize)
+}
+
+// values that don't convert, inserted by hand or by another tool, fail the query instead of panicking
+fn column<T: FromValue>(row: &Row, index: usize) -> Result<T, MError> {
+    let value = row.get_opt(index).unwrap_or_else(|| Err(FromValueError(Value::NULL)))?;
+    Ok(value)
+}
+
+fn invalid_value(text: String) -> MError {
+    MError::FromValueError(Value::Bytes(text.into_bytes()))
+}
+
+fn query_applied_migrations(transaction: &mut MTransaction, query: &str) -> Result<Vec<AppliedMigration>, MError> {
     let rows = transaction.query(query)?;
-    match rows.into_iter().next() {
-        None => Ok(None),
-        Some(Ok(row)) => {
-            let version: i64 = row.get(0).unwrap();
-            let _installed_on: String = row.get(2).unwrap();
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on)
-                .unwrap()
-                .with_timezone(&Local);
-
-            Ok(Some(AppliedMigration {
-                version: version as usize,
-                name: row.get(1).unwrap(),
-                installed_on,
-                checksum: row.get(3).unwrap(),
-            }))
-        }
-        Some(Err(err)) => Err(err),
+    let mut applied = Vec::new();
+    for row in rows {
+        let row = row?;
+        let version = parse_applied_version(column(&row, 0)?).map_err(invalid_value)?;
+        // the text protocol returns DATETIME as text
+        let installed_on = parse_installed_on(column(&row, 2)?).map_err(invalid_value)?;
+
+        applied.push(AppliedMigration {
+            name: column(&row, 1)?,
+            migration_type: AppliedMigrationType::from_column(column(&row, 5)?, &version),
+            description: column(&row, 6)?,
+            installed_on,
+            version,
+            // baseline rows are recorded without checksum
+            checksum: column::<Option<String>>(&row, 3)?.unwrap_or_default(),
+            // rows recorded before failed migrations were tracked have no success
+            success: column::<Option<bool>>(&row, 4)?.unwrap_or(true),
+        });
+    }
+    Ok(applied)
+}
+
+fn query_schema_history_columns(transaction: &mut MTransaction, table: &str) -> Result<Vec<SchemaColumn>, MError> {
+    let rows = transaction.prep_exec(
+        "SELECT column_name, data_type, is_nullable FROM information_schema.columns \
+         WHERE table_schema = DATABASE() AND table_name = ?",
+        (table,),
+    )?;
+    let mut columns = Vec::new();
+    for row in rows {
+        let row = row?;
+        let nullable: String = row.get(2).unwrap();
+        columns.push(SchemaColumn {
+            name: row.get(0).unwrap(),
+            data_type: row.get(1).unwrap(),
+            nullable: nullable == "YES",
+        });
     }
+    Ok(columns)
 }
 
 impl<'a> Transaction for MTransaction<'a> {
     type Error = MError;
+    const DIALECT: Dialect = Dialect::Mysql;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
-        let count = self.first_exec(query, Params::Empty)?;
-        Ok(count.unwrap_or(0) as usize)
+        execute_query(self, query)
+    }
+
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        execute_params(self, query, params)
     }
 }
 
@@ -45,12 +126,16 @@ impl<'a> CommitTransaction for MTransaction<'a> {
 }
 
 impl<'a> Query<AppliedMigration> for MTransaction<'a> {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
-        query_migration_version(self, query)
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
+        query_applied_migrations(self, query)
     }
 }
 
-impl<'a> DefaultQueries for MTransaction<'a> {}
+impl<'a> DefaultQueries for MTransaction<'a> {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        query_schema_history_columns(self, table)
+    }
+}
 
 impl<'a> MigrateGrouped<'a> for Conn {
     type Transaction = MTransaction<'a>;
@@ -73,72 +158,148 @@ impl<'a> MigrateGrouped<'a> for PooledConn {
 
 impl Transaction for Conn {
     type Error = MError;
+    const DIALECT: Dialect = Dialect::Mysql;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
         let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let count = transaction.first_exec(query, Params::Empty)?;
+        let count = execute_query(&mut transaction, query)?;
         transaction.commit()?;
-        Ok(count.unwrap_or(0) as usize)
+        Ok(count)
+    }
+
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
+        let count = execute_params(&mut transaction, query, params)?;
+        transaction.commit()?;
+        Ok(count)
     }
 }
 
 impl Transaction for PooledConn {
     type Error = MError;
+    const DIALECT: Dialect = Dialect::Mysql;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
         let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let count = transaction.first_exec(query, Params::Empty)?;
+        let count = execute_query(&mut transaction, query)?;
         transaction.commit()?;
-        Ok(count.unwrap_or(0) as usize)
+        Ok(count)
+    }
+
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
+        let count = execute_params(&mut transaction, query, params)?;
+        transaction.commit()?;
+        Ok(count)
     }
 }
 
 impl ExecuteMultiple for Conn {
-    fn execute_multiple(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
-        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let mut count = 0;
-        for query in queries.iter() {
-            count += transaction.first_exec(query, Params::Empty)?.unwrap_or(0);
+    fn execute_multiple(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut transaction = self
+            .start_transaction(true, Some(IsolationLevel::RepeatableRead), None)
+            .map_err(|err| (queries.len(), err))?;
+        let mut counts = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            counts.push(execute_params(&mut transaction, query, params).map_err(|err| (index, err))?);
         }
-        transaction.commit()?;
-        Ok(count as usize)
+        transaction.commit().map_err(|err| (queries.len(), err))?;
+        Ok(counts)
+    }
+
+    fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            let result = Conn::query(self, query).map_err(|err| (index, err))?;
+            counts.push(result.affected_rows() as usize);
+        }
+        Ok(counts)
     }
 }
 
 impl ExecuteMultiple for PooledConn {
-    fn execute_multiple(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
-        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let mut count = 0;
-        for query in queries.iter() {
-            count += transaction.first_exec(query, Params::Empty)?.unwrap_or(0);
+    fn execute_multiple(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut transaction = self
+            .start_transaction(true, Some(IsolationLevel::RepeatableRead), None)
+            .map_err(|err| (queries.len(), err))?;
+        let mut counts = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            counts.push(execute_params(&mut transaction, query, params).map_err(|err| (index, err))?);
         }
-        transaction.commit()?;
-        Ok(count as usize)
+        transaction.commit().map_err(|err| (queries.len(), err))?;
+        Ok(counts)
+    }
+
+    fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            let result = PooledConn::query(self, query).map_err(|err| (index, err))?;
+            counts.push(result.affected_rows() as usize);
+        }
+        Ok(counts)
     }
 }
 
 impl Query<AppliedMigration> for Conn {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
         let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let version = query_migration_version(&mut transaction, query)?;
+        let applied = query_applied_migrations(&mut transaction, query)?;
         transaction.commit()?;
-        Ok(version)
+        Ok(applied)
     }
 }
 
 impl Query<AppliedMigration> for PooledConn {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
         let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
-        let version = query_migration_version(&mut transaction, query)?;
+        let applied = query_applied_migrations(&mut transaction, query)?;
         transaction.commit()?;
-        Ok(version)
+        Ok(applied)
     }
 }
 
-impl DefaultQueries for Conn {}
+impl DefaultQueries for Conn {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
+        let columns = query_schema_history_columns(&mut transaction, table)?;
+        transaction.commit()?;
+        Ok(columns)
+    }
+}
 
-impl DefaultQueries for PooledConn {}
+impl DefaultQueries for PooledConn {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let mut transaction = self.start_transaction(true, Some(IsolationLevel::RepeatableRead), None)?;
+        let columns = query_schema_history_columns(&mut transaction, table)?;
+        transaction.commit()?;
+        Ok(columns)
+    }
+}
 
 impl Migrate for Conn {}
 
 impl Migrate for PooledConn {}
+
+impl Lock for Conn {
+    fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        let locked: Option<Option<i64>> = self.first_exec(lock_query(timeout), Params::Empty)?;
+        Ok(locked == Some(Some(1)))
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        self.prep_exec(format!("SELECT RELEASE_LOCK('{}')", LOCK_NAME), Params::Empty)?;
+        Ok(())
+    }
+}
+
+impl Lock for PooledConn {
+    fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        let locked: Option<Option<i64>> = self.first_exec(lock_query(timeout), Params::Empty)?;
+        Ok(locked == Some(Some(1)))
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        self.prep_exec(format!("SELECT RELEASE_LOCK('{}')", LOCK_NAME), Params::Empty)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::traits::{parse_applied_version, parse_installed_on};
+use crate::{
+    AppliedMigration, AppliedMigrationType, AsyncLock, AsyncMigrate, AsyncQuery, AsyncTransaction, Dialect, Param,
+    SchemaColumn,
+};
+use async_trait::async_trait;
+use chrono::{Datelike, Timelike};
+use std::time::{Duration, Instant};
+use mysql_async::{
+    prelude::{FromValue, Queryable}, Conn, DriverError, Error as MError, FromValueError, IsolationLevel, Params, Row,
+    TxOpts, Value,
+};
+
+// same lock name as the mysql backend, so sync and async runs exclude each other
+const LOCK_NAME: &str = "keg_schema_history";
+
+fn to_values(params: &[Param]) -> Vec<Value> {
+    params
+        .iter()
+        .map(|param| match param {
+            Param::Text(text) => text.as_ref().map_or(Value::NULL, |text| Value::Bytes(text.clone().into_bytes())),
+            Param::Int(value) => value.map_or(Value::NULL, Value::Int),
+            Param::Bool(value) => Value::Int(*value as i64),
+            Param::Timestamp(value) => Value::Date(
+                value.year() as u16,
+                value.month() as u8,
+                value.day() as u8,
+                value.hour() as u8,
+                value.minute() as u8,
+                value.second() as u8,
+                value.nanosecond() / 1000,
+            ),
+        })
+        .collect()
+}
+
+fn transaction_opts() -> TxOpts {
+    let mut opts = TxOpts::default();
+    opts.with_consistent_snapshot(true)
+        .with_isolation_level(Some(IsolationLevel::RepeatableRead));
+    opts
+}
+
+#[async_trait]
+impl AsyncTransaction for Conn {
+    type Error = MError;
+    const DIALECT: Dialect = Dialect::Mysql;
+
+    async fn execute_timed(
+        &mut self,
+        queries: &[(&str, &[Param])],
+    ) -> Result<Vec<(usize, Duration)>, (usize, Self::Error)> {
+        let mut transaction = self
+            .start_transaction(transaction_opts())
+            .await
+            .map_err(|err| (queries.len(), err))?;
+        let mut executed = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            let start = Instant::now();
+            // history queries with params are prepared to bind them, migration statements run as text
+            let result = if params.is_empty() {
+                transaction.query_drop(*query).await
+            } else {
+                transaction.exec_drop(*query, Params::Positional(to_values(params))).await
+            };
+            result.map_err(|err| (index, err))?;
+            executed.push((transaction.affected_rows() as usize, start.elapsed()));
+        }
+        transaction.commit().await.map_err(|err| (queries.len(), err))?;
+        Ok(executed)
+    }
+
+    async fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            self.query_drop(*query).await.map_err(|err| (index, err))?;
+            counts.push(self.affected_rows() as usize);
+        }
+        Ok(counts)
+    }
+}
+
+// values that don't convert, inserted by hand or by another tool, fail the query instead of panicking
+fn column<T: FromValue>(row: &Row, index: usize) -> Result<T, MError> {
+    match row.get_opt(index) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(FromValueError(value))) => Err(DriverError::FromValue { value }.into()),
+        None => Err(DriverError::FromValue { value: Value::NULL }.into()),
+    }
+}
+
+fn invalid_value(text: String) -> MError {
+    DriverError::FromValue { value: Value::Bytes(text.into_bytes()) }.into()
+}
+
+#[async_trait]
+impl AsyncQuery<AppliedMigration> for Conn {
+    async fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
+        let mut transaction = self.start_transaction(transaction_opts()).await?;
+        let rows: Vec<Row> = transaction.query(query).await?;
+        transaction.commit().await?;
+
+        let mut applied = Vec::new();
+        for row in rows.into_iter() {
+            let version = parse_applied_version(column(&row, 0)?).map_err(invalid_value)?;
+            // the text protocol returns DATETIME as text
+            let installed_on = parse_installed_on(column(&row, 2)?).map_err(invalid_value)?;
+
+            applied.push(AppliedMigration {
+                name: column(&row, 1)?,
+                migration_type: AppliedMigrationType::from_column(column(&row, 5)?, &version),
+                description: column(&row, 6)?,
+                installed_on,
+                version,
+                // baseline rows are recorded without checksum
+                checksum: column::<Option<String>>(&row, 3)?.unwrap_or_default(),
+                // rows recorded before failed migrations were tracked have no success
+                success: column::<Option<bool>>(&row, 4)?.unwrap_or(true),
+            });
+        }
+        Ok(applied)
+    }
+}
+
+#[async_trait]
+impl AsyncMigrate for Conn {
+    async fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let rows: Vec<Row> = Queryable::exec(
+            self,
+            "SELECT column_name, data_type, is_nullable FROM information_schema.columns \
+             WHERE table_schema = DATABASE() AND table_name = ?",
+            (table,),
+        )
+        .await?;
+        let mut columns = Vec::new();
+        for row in rows.into_iter() {
+            let nullable: String = row.get(2).unwrap();
+            columns.push(SchemaColumn {
+                name: row.get(0).unwrap(),
+                data_type: row.get(1).unwrap(),
+                nullable: nullable == "YES",
+            });
+        }
+        Ok(columns)
+    }
+}
+
+#[async_trait]
+impl AsyncLock for Conn {
+    // GET_LOCK waits the given number of seconds, 1 is returned when the lock is taken
+    async fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        let seconds = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+        let locked: Option<Option<i64>> = self.exec_first("SELECT GET_LOCK(?, ?)", (LOCK_NAME, seconds)).await?;
+        Ok(locked == Some(Some(1)))
+    }
+
+    async fn unlock(&mut self) -> Result<(), Self::Error> {
+        self.exec_drop("SELECT RELEASE_LOCK(?)", (LOCK_NAME,)).await?;
+        Ok(())
+    }
+}
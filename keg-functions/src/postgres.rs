@@ -1,40 +1,109 @@
 use crate::{
     CommitTransaction, DefaultQueries, Migrate, MigrateGrouped, Error,
-    AppliedMigration, Query, Transaction, WrapMigrationError, ExecuteMultiple
+    AppliedMigration, AppliedMigrationType, SchemaColumn, Lock, Query, Transaction, WrapMigrationError, ExecuteMultiple, Dialect, Param,
+    Version,
 };
-use chrono::{DateTime, Local};
+use crate::statements::numbered_placeholders;
+use crate::traits::poll_lock;
+use chrono::{DateTime, Local, Utc};
 use postgres::{
-    transaction::Transaction as PgTransaction, Connection as PgConnection, Error as PgError,
+    transaction::Transaction as PgTransaction, types::{FromSql, ToSql, Type}, Connection as PgConnection,
+    Error as PgError,
 };
+use std::time::Duration;
 
-fn query_migration_version(transaction: &PgTransaction, query: &str) -> Result<Option<AppliedMigration>, PgError> {
+// advisory lock key shared by every keg run, "keg" in ascii
+const LOCK_KEY: i64 = 0x006b_6567;
+
+fn to_sql_params(params: &[Param]) -> Vec<&dyn ToSql> {
+    params
+        .iter()
+        .map(|param| -> &dyn ToSql {
+            match param {
+                Param::Text(text) => text,
+                Param::Int(value) => value,
+                Param::Bool(value) => value,
+                Param::Timestamp(value) => value,
+            }
+        })
+        .collect()
+}
+
+// migration statements are executed without params and are left as is, as they may use ? as an operator
+fn execute_params(transaction: &PgTransaction, query: &str, params: &[Param]) -> Result<usize, PgError> {
+    let count = if params.is_empty() {
+        PgTransaction::execute(transaction, query, &[])?
+    } else {
+        PgTransaction::execute(transaction, &numbered_placeholders(query), &to_sql_params(params))?
+    };
+    Ok(count as usize)
+}
+
+// a version that doesn't parse, inserted by hand or by another tool, fails the query instead of panicking
+impl FromSql for Version {
+    fn from_sql(ty: &Type, raw: &[u8]) -> Result<Version, Box<dyn std::error::Error + Sync + Send>> {
+        let text = String::from_sql(ty, raw)?;
+        Ok(text.parse()?)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <String as FromSql>::accepts(ty)
+    }
+}
+
+fn query_applied_migrations(transaction: &PgTransaction, query: &str) -> Result<Vec<AppliedMigration>, PgError> {
     let rows = transaction.query(query, &[])?;
-    match rows.into_iter().next() {
-        None => Ok(None),
-        Some(row) => {
-            let version: i32 = row.get(0);
-            let _installed_on: String = row.get(2);
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on)
-                .unwrap()
-                .with_timezone(&Local);
-
-            Ok(Some(AppliedMigration {
-                version: version as usize,
-                name: row.get(1),
-                installed_on,
-                checksum: row.get(3),
-            }))
-        }
+    let mut applied = Vec::new();
+    for row in rows.into_iter() {
+        // repeatable migrations are recorded without version
+        let version: Version = row.get_opt::<_, Option<Version>>(0).unwrap_or(Ok(None))?.unwrap_or_default();
+        let installed_on: Option<DateTime<Utc>> = row.get(2);
+
+        applied.push(AppliedMigration {
+            name: row.get(1),
+            migration_type: AppliedMigrationType::from_column(row.get(5), &version),
+            description: row.get(6),
+            installed_on: installed_on.map(|installed_on| installed_on.with_timezone(&Local)),
+            version,
+            // baseline rows are recorded without checksum
+            checksum: row.get::<_, Option<String>>(3).unwrap_or_default(),
+            // rows recorded before failed migrations were tracked have no success
+            success: row.get::<_, Option<bool>>(4).unwrap_or(true),
+        });
     }
+    Ok(applied)
+}
+
+fn query_schema_history_columns(transaction: &PgTransaction, table: &str) -> Result<Vec<SchemaColumn>, PgError> {
+    let rows = transaction.query(
+        "SELECT column_name::text, data_type::text, is_nullable::text FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND table_name = $1",
+        &[&table],
+    )?;
+    let mut columns = Vec::new();
+    for row in rows.into_iter() {
+        let nullable: String = row.get(2);
+        columns.push(SchemaColumn {
+            name: row.get(0),
+            data_type: row.get(1),
+            nullable: nullable == "YES",
+        });
+    }
+    Ok(columns)
 }
 
 impl<'a> Transaction for PgTransaction<'a> {
     type Error = PgError;
+    const DIALECT: Dialect = Dialect::Postgres;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
         let count = PgTransaction::execute(self, query, &[])?;
         Ok(count as usize)
     }
+
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        execute_params(self, query, params)
+    }
 }
 
 impl<'a> CommitTransaction for PgTransaction<'a> {
@@ -44,12 +113,16 @@ impl<'a> CommitTransaction for PgTransaction<'a> {
 }
 
 impl<'a> Query<AppliedMigration> for PgTransaction<'a> {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
-        query_migration_version(self, query)
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
+        query_applied_migrations(self, query)
     }
 }
 
-impl<'a> DefaultQueries for PgTransaction<'a> {}
+impl<'a> DefaultQueries for PgTransaction<'a> {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        query_schema_history_columns(self, table)
+    }
+}
 
 impl<'a> MigrateGrouped<'a> for PgConnection {
     type Transaction = PgTransaction<'a>;
@@ -61,6 +134,7 @@ impl<'a> MigrateGrouped<'a> for PgConnection {
 
 impl Transaction for PgConnection {
     type Error = PgError;
+    const DIALECT: Dialect = Dialect::Postgres;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
         let mut transaction = PgConnection::transaction(&self)?;
@@ -68,29 +142,80 @@ impl Transaction for PgConnection {
         transaction.commit()?;
         Ok(count as usize)
     }
-}
 
-impl ExecuteMultiple for PgConnection {
-    fn execute_multiple(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
-        let mut transaction = PgConnection::transaction(&self)?;
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        let transaction = PgConnection::transaction(&self)?;
+        let count = execute_params(&transaction, query, params)?;
+        transaction.commit()?;
+        Ok(count)
+    }
+
+    fn execute_batch(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
+        let transaction = PgConnection::transaction(&self)?;
         let mut count = 0;
         for query in queries.iter() {
-            count += PgTransaction::execute(&mut transaction, query, &[])?;
+            count += PgTransaction::execute(&transaction, query, &[])?;
         }
         transaction.commit()?;
         Ok(count as usize)
     }
 }
 
+impl ExecuteMultiple for PgConnection {
+    fn execute_multiple(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut transaction = PgConnection::transaction(&self).map_err(|err| (queries.len(), err))?;
+        let mut counts = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            counts.push(transaction.execute_params(query, params).map_err(|err| (index, err))?);
+        }
+        transaction.commit().map_err(|err| (queries.len(), err))?;
+        Ok(counts)
+    }
+
+    fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            let count = PgConnection::execute(self, query, &[]).map_err(|err| (index, err))?;
+            counts.push(count as usize);
+        }
+        Ok(counts)
+    }
+}
+
 impl Query<AppliedMigration> for PgConnection {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
         let transaction = PgConnection::transaction(self)?;
-        let version = query_migration_version(&transaction, query)?;
+        let applied = query_applied_migrations(&transaction, query)?;
         transaction.commit()?;
-        Ok(version)
+        Ok(applied)
     }
 }
 
-impl DefaultQueries for PgConnection {}
+impl DefaultQueries for PgConnection {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let transaction = PgConnection::transaction(self)?;
+        let columns = query_schema_history_columns(&transaction, table)?;
+        transaction.commit()?;
+        Ok(columns)
+    }
+}
 
 impl Migrate for PgConnection {}
+
+impl Lock for PgConnection {
+    fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        let query = format!("SELECT pg_try_advisory_lock({})", LOCK_KEY);
+        poll_lock(timeout, || {
+            let rows = PgConnection::query(self, &query, &[])?;
+            match rows.iter().next() {
+                Some(row) => Ok(row.get(0)),
+                None => Ok(false),
+            }
+        })
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        PgConnection::execute(self, &format!("SELECT pg_advisory_unlock({})", LOCK_KEY), &[])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{Migration, Version};
+use chrono::{DateTime, Local};
+use std::time::Duration;
+
+/// Migration applied by a run
+#[derive(Clone, Debug)]
+pub struct MigrationReport {
+    version: Option<Version>,
+    name: String,
+    checksum: u32,
+    started_on: DateTime<Local>,
+    duration: Duration,
+    rows_affected: usize,
+}
+
+impl MigrationReport {
+    pub(crate) fn new(
+        migration: &Migration,
+        started_on: DateTime<Local>,
+        duration: Duration,
+        rows_affected: usize,
+    ) -> MigrationReport {
+        MigrationReport {
+            version: migration.version().cloned(),
+            name: migration.name().to_string(),
+            checksum: migration.checksum(),
+            started_on,
+            duration,
+            rows_affected,
+        }
+    }
+
+    /// Version of the migration, repeatable migrations don't have one
+    pub fn version(&self) -> Option<&Version> {
+        self.version.as_ref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn started_on(&self) -> DateTime<Local> {
+        self.started_on
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Rows affected by the migration sql as reported by the database
+    pub fn rows_affected(&self) -> usize {
+        self.rows_affected
+    }
+}
+
+/// Migrations applied by a run, in the order they were applied
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    applied: Vec<MigrationReport>,
+}
+
+impl Report {
+    pub(crate) fn push(&mut self, migration: MigrationReport) {
+        self.applied.push(migration);
+    }
+
+    pub fn applied(&self) -> &[MigrationReport] {
+        &self.applied
+    }
+}
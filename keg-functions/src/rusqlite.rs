@@ -1,39 +1,86 @@
 use crate::{
     CommitTransaction, DefaultQueries, Migrate, MigrateGrouped, Error,
-    AppliedMigration, Query, Transaction, WrapMigrationError, ExecuteMultiple
+    AppliedMigration, AppliedMigrationType, SchemaColumn, Lock, Query, Transaction, WrapMigrationError, ExecuteMultiple, Dialect, Param,
+    Version,
 };
-use chrono::{DateTime, Local};
+use crate::traits::{parse_installed_on, poll_lock};
+use chrono::Utc;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Type, ValueRef};
 use rusqlite::{
-    Connection as RqlConnection, Error as RqlError, OptionalExtension,
-    Transaction as RqlTransaction, NO_PARAMS,
+    Connection as RqlConnection, Error as RqlError, ErrorCode, Transaction as RqlTransaction, NO_PARAMS,
 };
+use std::time::Duration;
 
-fn query_migration_version(transaction: &RqlConnection, query: &str) -> Result<Option<AppliedMigration>, RqlError> {
-        transaction.query_row(query, NO_PARAMS, |row| {
-            //FromSql not implemented for usize
-            let version: isize = row.get(0)?;
-            let _installed_on: String = row.get(2)?;
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on)
-                .unwrap()
-                .with_timezone(&Local);
-            let mig = AppliedMigration {
-                version: version as usize,
-                name: row.get(1)?,
-                installed_on,
-                checksum: row.get(3)?,
-            };
-            Ok(mig)
+impl ToSql for Param {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, RqlError> {
+        match self {
+            Param::Text(text) => text.to_sql(),
+            Param::Int(value) => value.to_sql(),
+            Param::Bool(value) => value.to_sql(),
+            // SQLite has no timestamp type, its date functions read this text
+            Param::Timestamp(value) => Ok(ToSqlOutput::from(value.format("%Y-%m-%d %H:%M:%S%.6f").to_string())),
+        }
+    }
+}
+
+// a version that doesn't parse, inserted by hand or by another tool, fails the query instead of panicking
+impl FromSql for Version {
+    fn column_result(value: ValueRef) -> FromSqlResult<Version> {
+        value.as_str()?.parse().map_err(|err: Error| FromSqlError::Other(Box::new(err)))
+    }
+}
+
+fn query_applied_migrations(transaction: &RqlConnection, query: &str) -> Result<Vec<AppliedMigration>, RqlError> {
+    let mut stmt = transaction.prepare(query)?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        // repeatable migrations are recorded without version
+        let version: Version = row.get::<_, Option<Version>>(0)?.unwrap_or_default();
+        let installed_on = parse_installed_on(row.get(2)?).map_err(|installed_on| {
+            RqlError::FromSqlConversionFailure(2, Type::Text, format!("invalid installed_on {}", installed_on).into())
+        })?;
+        Ok(AppliedMigration {
+            name: row.get(1)?,
+            migration_type: AppliedMigrationType::from_column(row.get(5)?, &version),
+            description: row.get(6)?,
+            installed_on,
+            version,
+            // baseline rows are recorded without checksum
+            checksum: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+            // rows recorded before failed migrations were tracked have no success
+            success: row.get::<_, Option<bool>>(4)?.unwrap_or(true),
+        })
+    })?;
+    rows.collect()
+}
+
+// PRAGMA takes no params, the table is one of keg's own
+fn query_schema_history_columns(transaction: &RqlConnection, table: &str) -> Result<Vec<SchemaColumn>, RqlError> {
+    let mut stmt = transaction.prepare(&format!("PRAGMA table_info({})", table))?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        let notnull: bool = row.get(3)?;
+        // INTEGER PRIMARY KEY columns are reported as nullable
+        let pk: bool = row.get(5)?;
+        Ok(SchemaColumn {
+            name: row.get(1)?,
+            data_type: row.get(2)?,
+            nullable: !notnull && !pk,
         })
-        .optional()
+    })?;
+    rows.collect()
 }
 
 impl<'a> Transaction for RqlTransaction<'a> {
     type Error = RqlError;
+    const DIALECT: Dialect = Dialect::Sqlite;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
         //Deref<Target = Connection>
         RqlConnection::execute(self, query, NO_PARAMS)
     }
+
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        RqlConnection::execute(self, query, params)
+    }
 }
 
 impl<'a> CommitTransaction for RqlTransaction<'a> {
@@ -43,12 +90,16 @@ impl<'a> CommitTransaction for RqlTransaction<'a> {
 }
 
 impl<'a> Query<AppliedMigration> for RqlTransaction<'a> {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
-        query_migration_version(self, query)
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
+        query_applied_migrations(self, query)
     }
 }
 
-impl<'a> DefaultQueries for RqlTransaction<'a> {}
+impl<'a> DefaultQueries for RqlTransaction<'a> {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        query_schema_history_columns(self, table)
+    }
+}
 
 impl<'a> MigrateGrouped<'a> for RqlConnection {
     type Transaction = RqlTransaction<'a>;
@@ -60,36 +111,116 @@ impl<'a> MigrateGrouped<'a> for RqlConnection {
 
 impl Transaction for RqlConnection {
     type Error = RqlError;
+    const DIALECT: Dialect = Dialect::Sqlite;
 
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error> {
-        let mut transaction = self.transaction()?;
-        let count = RqlConnection::execute(&mut transaction, query, NO_PARAMS)?;
+        let transaction = self.transaction()?;
+        let count = RqlConnection::execute(&transaction, query, NO_PARAMS)?;
         transaction.commit()?;
         Ok(count)
     }
-}
 
-impl ExecuteMultiple for RqlConnection {
-    fn execute_multiple(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
-        let mut transaction = self.transaction()?;
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error> {
+        let transaction = self.transaction()?;
+        let count = RqlConnection::execute(&transaction, query, params)?;
+        transaction.commit()?;
+        Ok(count)
+    }
+
+    fn execute_batch(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
+        let transaction = self.transaction()?;
         let mut count = 0;
         for query in queries.iter() {
-            count += transaction.execute(query)?;
+            count += RqlConnection::execute(&transaction, query, NO_PARAMS)?;
         }
         transaction.commit()?;
         Ok(count)
     }
 }
 
+impl ExecuteMultiple for RqlConnection {
+    fn execute_multiple(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut transaction = self.transaction().map_err(|err| (queries.len(), err))?;
+        let mut counts = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            counts.push(transaction.execute_params(query, params).map_err(|err| (index, err))?);
+        }
+        transaction.commit().map_err(|err| (queries.len(), err))?;
+        Ok(counts)
+    }
+
+    fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            counts.push(RqlConnection::execute(self, query, NO_PARAMS).map_err(|err| (index, err))?);
+        }
+        Ok(counts)
+    }
+}
+
 impl Query<AppliedMigration> for RqlConnection {
-    fn query(&mut self, query: &str) -> Result<Option<AppliedMigration>, Self::Error> {
+    fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
         let transaction = self.transaction()?;
-        let version = query_migration_version(&transaction, query)?;
+        let applied = query_applied_migrations(&transaction, query)?;
         transaction.commit()?;
-        Ok(version)
+        Ok(applied)
     }
 }
 
-impl DefaultQueries for RqlConnection {}
+impl DefaultQueries for RqlConnection {
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let transaction = self.transaction()?;
+        let columns = query_schema_history_columns(&transaction, table)?;
+        transaction.commit()?;
+        Ok(columns)
+    }
+}
 
 impl Migrate for RqlConnection {}
+
+// SQLite has no session locks, so the lock is a row in keg_schema_history_lock. A run that didn't finish
+// leaves it behind, it's only taken over when the runner is given how old a lock has to be to be stale,
+// as the row can't tell a crashed run from a live one taking long
+fn lock_row(conn: &mut RqlConnection, timeout: Duration, stale_after: Option<Duration>) -> Result<bool, RqlError> {
+    RqlConnection::execute(
+        conn,
+        "CREATE TABLE IF NOT EXISTS keg_schema_history_lock(id INTEGER PRIMARY KEY, locked_on DATETIME)",
+        NO_PARAMS,
+    )?;
+    let stale_after = stale_after.and_then(|stale_after| chrono::Duration::from_std(stale_after).ok());
+    poll_lock(timeout, || {
+        if let Some(stale_on) = stale_after.and_then(|stale_after| Utc::now().checked_sub_signed(stale_after)) {
+            let stale_on = Param::Timestamp(stale_on);
+            RqlConnection::execute(conn, "DELETE FROM keg_schema_history_lock WHERE locked_on < ?", &[&stale_on])?;
+        }
+        let locked_on = Param::Timestamp(Utc::now());
+        match RqlConnection::execute(
+            conn,
+            "INSERT INTO keg_schema_history_lock (id, locked_on) VALUES (1, ?)",
+            &[&locked_on],
+        ) {
+            Ok(_) => Ok(true),
+            Err(RqlError::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation || err.code == ErrorCode::DatabaseBusy =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    })
+}
+
+impl Lock for RqlConnection {
+    fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        lock_row(self, timeout, None)
+    }
+
+    fn lock_taking_over_stale(&mut self, timeout: Duration, stale_after: Duration) -> Result<bool, Self::Error> {
+        lock_row(self, timeout, Some(stale_after))
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        RqlConnection::execute(self, "DELETE FROM keg_schema_history_lock", NO_PARAMS)?;
+        Ok(())
+    }
+}
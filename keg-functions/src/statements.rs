@@ -0,0 +1,339 @@
+/// SQL dialect of a backend, it decides which quotes, comments and blocks
+/// can contain a semicolon without ending the statement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// single and double quoted strings, backticks, line and block comments
+    Generic,
+    /// also dollar quoted bodies, `BEGIN ATOMIC ... END` bodies, escape strings and nested block comments
+    Postgres,
+    /// also backslash escapes, `#` comments, `/*! ... */` executable comments and `DELIMITER` directives,
+    /// `--` only starts a comment when followed by whitespace
+    Mysql,
+    /// also `CREATE TRIGGER ... BEGIN ... END` bodies
+    Sqlite,
+}
+
+/// Splits a sql script into its statements on the semicolons, or the current `DELIMITER` on MySQL,
+/// outside of quotes, comments and blocks. Chunks with nothing but whitespace and comments are skipped,
+/// except for MySQL executable comments and optimizer hints which run as statements
+pub fn split_statements(sql: &str, dialect: Dialect) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut delimiter = ";".to_string();
+    let mut start = 0;
+    let mut has_code = false;
+    // leading words of the statement, to find sqlite triggers
+    let mut words: Vec<String> = Vec::new();
+    // open BEGIN and CASE blocks of a sqlite trigger, or BEGIN ATOMIC and CASE blocks of a postgres body
+    let mut depth = 0;
+    // previous word, to find postgres BEGIN ATOMIC
+    let mut previous = String::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if dialect == Dialect::Mysql && is_delimiter_directive(bytes, i) {
+            if has_code {
+                statements.push(sql[start..i].trim());
+            }
+            let end = line_end(bytes, i);
+            delimiter = sql[i + "DELIMITER".len()..end].trim().to_string();
+            i = end;
+            start = end;
+            has_code = false;
+            continue;
+        }
+
+        if depth == 0 && !delimiter.is_empty() && bytes[i..].starts_with(delimiter.as_bytes()) {
+            if has_code {
+                statements.push(sql[start..i].trim());
+            }
+            i += delimiter.len();
+            start = i;
+            has_code = false;
+            words.clear();
+            continue;
+        }
+
+        let next = bytes.get(i + 1).cloned();
+        i = match bytes[i] {
+            quote @ b'\'' | quote @ b'"' => {
+                has_code = true;
+                skip_quoted(bytes, i, quote, dialect == Dialect::Mysql)
+            }
+            b'`' => {
+                has_code = true;
+                skip_quoted(bytes, i, b'`', false)
+            }
+            b'-' if next == Some(b'-') && (dialect != Dialect::Mysql || is_mysql_comment_start(bytes, i)) => {
+                line_end(bytes, i)
+            }
+            b'#' if dialect == Dialect::Mysql => line_end(bytes, i),
+            b'/' if next == Some(b'*') => {
+                // executable comments and optimizer hints are run by MySQL
+                if dialect == Dialect::Mysql && (bytes.get(i + 2) == Some(&b'!') || bytes.get(i + 2) == Some(&b'+')) {
+                    has_code = true;
+                }
+                skip_block_comment(bytes, i, dialect == Dialect::Postgres)
+            }
+            b'$' if dialect == Dialect::Postgres => {
+                has_code = true;
+                skip_dollar_quoted(sql, i).unwrap_or(i + 1)
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                has_code = true;
+                let end = word_end(bytes, i, dialect);
+                let word = sql[i..end].to_ascii_uppercase();
+                if dialect == Dialect::Postgres && word == "E" && bytes.get(end) == Some(&b'\'') {
+                    // escape strings allow backslash escapes
+                    skip_quoted(bytes, end, b'\'', true)
+                } else {
+                    if dialect == Dialect::Sqlite {
+                        if is_trigger(&words) {
+                            match word.as_str() {
+                                "BEGIN" | "CASE" => depth += 1,
+                                "END" if depth > 0 => depth -= 1,
+                                _ => (),
+                            }
+                        } else if words.len() < 3 {
+                            words.push(word);
+                        }
+                    } else if dialect == Dialect::Postgres {
+                        match word.as_str() {
+                            "ATOMIC" if previous == "BEGIN" => depth += 1,
+                            "CASE" if depth > 0 => depth += 1,
+                            "END" if depth > 0 => depth -= 1,
+                            _ => (),
+                        }
+                        previous = word;
+                    }
+                    end
+                }
+            }
+            c => {
+                if !c.is_ascii_whitespace() {
+                    has_code = true;
+                }
+                i + 1
+            }
+        };
+    }
+
+    if has_code {
+        statements.push(sql[start..].trim());
+    }
+    statements
+}
+
+// rewrites the `?` placeholders of a schema history query to the numbered ones of Postgres,
+// history queries have no question marks in quotes or comments
+#[cfg(any(feature = "postgres", all(feature = "async", feature = "tokio-postgres")))]
+pub(crate) fn numbered_placeholders(query: &str) -> String {
+    let mut numbered = String::with_capacity(query.len());
+    let mut count = 0;
+    for c in query.chars() {
+        if c == '?' {
+            count += 1;
+            numbered.push_str(&format!("${}", count));
+        } else {
+            numbered.push(c);
+        }
+    }
+    numbered
+}
+
+// CREATE [TEMP | TEMPORARY] TRIGGER
+fn is_trigger(words: &[String]) -> bool {
+    let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+    words == ["CREATE", "TRIGGER"] || words == ["CREATE", "TEMP", "TRIGGER"] || words == ["CREATE", "TEMPORARY", "TRIGGER"]
+}
+
+// MySQL only starts a comment on `--` followed by whitespace or a control character, `1--1` is a subtraction
+fn is_mysql_comment_start(bytes: &[u8], i: usize) -> bool {
+    match bytes.get(i + 2) {
+        Some(c) => c.is_ascii_whitespace() || c.is_ascii_control(),
+        None => true,
+    }
+}
+
+// DELIMITER at the start of a line followed by the new delimiter
+fn is_delimiter_directive(bytes: &[u8], i: usize) -> bool {
+    let directive = b"DELIMITER";
+    bytes.len() > i + directive.len()
+        && bytes[i..i + directive.len()].eq_ignore_ascii_case(directive)
+        && bytes[i + directive.len()].is_ascii_whitespace()
+        && bytes[..i].iter().rev().take_while(|c| **c != b'\n').all(u8::is_ascii_whitespace)
+}
+
+fn line_end(bytes: &[u8], i: usize) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|c| *c == b'\n')
+        .map(|position| i + position + 1)
+        .unwrap_or_else(|| bytes.len())
+}
+
+fn word_end(bytes: &[u8], i: usize, dialect: Dialect) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|c| !(c.is_ascii_alphanumeric() || *c == b'_' || (*c == b'$' && dialect == Dialect::Postgres)))
+        .map(|position| i + position)
+        .unwrap_or_else(|| bytes.len())
+}
+
+// a doubled quote escapes it, which is the same as closing and reopening the quote
+fn skip_quoted(bytes: &[u8], i: usize, quote: u8, backslash_escapes: bool) -> usize {
+    let mut j = i + 1;
+    while j < bytes.len() {
+        if bytes[j] == b'\\' && backslash_escapes {
+            j += 2;
+        } else if bytes[j] == quote {
+            return j + 1;
+        } else {
+            j += 1;
+        }
+    }
+    bytes.len()
+}
+
+fn skip_block_comment(bytes: &[u8], i: usize, nested: bool) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j + 1 < bytes.len() {
+        if bytes[j] == b'/' && bytes[j + 1] == b'*' && (nested || depth == 0) {
+            depth += 1;
+            j += 2;
+        } else if bytes[j] == b'*' && bytes[j + 1] == b'/' {
+            depth -= 1;
+            j += 2;
+            if depth == 0 {
+                return j;
+            }
+        } else {
+            j += 1;
+        }
+    }
+    bytes.len()
+}
+
+// $tag$ ... $tag$ where tag is empty or an identifier, None if i doesn't start a dollar quote
+fn skip_dollar_quoted(sql: &str, i: usize) -> Option<usize> {
+    let bytes = sql.as_bytes();
+    let tag_len = bytes[i + 1..]
+        .iter()
+        .position(|c| !(c.is_ascii_alphanumeric() || *c == b'_'))?;
+    let tag_end = i + 1 + tag_len;
+    if bytes[tag_end] != b'$' || (tag_len > 0 && bytes[i + 1].is_ascii_digit()) {
+        return None;
+    }
+    let tag = &sql[i..=tag_end];
+    let body = tag_end + 1;
+    Some(match sql[body..].find(tag) {
+        Some(position) => body + position + tag.len(),
+        None => sql.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_statements, Dialect};
+
+    #[test]
+    fn splits_statements() {
+        let sql = "CREATE TABLE cars(id int);\nINSERT INTO cars VALUES (1);\n\nSELECT 1";
+        assert_eq!(
+            vec!["CREATE TABLE cars(id int)", "INSERT INTO cars VALUES (1)", "SELECT 1"],
+            split_statements(sql, Dialect::Generic)
+        );
+    }
+
+    #[test]
+    fn ignores_semicolons_in_quotes_and_comments() {
+        let sql = "-- drop; the table\nINSERT INTO notes VALUES ('a;b', \"c;d\", 'it''s;');\n/* ; */ SELECT 1; -- done;";
+        assert_eq!(
+            vec![
+                "-- drop; the table\nINSERT INTO notes VALUES ('a;b', \"c;d\", 'it''s;')",
+                "/* ; */ SELECT 1",
+            ],
+            split_statements(sql, Dialect::Generic)
+        );
+    }
+
+    #[test]
+    fn splits_postgres_dollar_quoted_functions() {
+        let sql = "CREATE FUNCTION one() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql;\n\
+                   DO $$ BEGIN PERFORM one(); END $$;\n\
+                   SELECT E'it\\'s;' /* a /* nested; */ comment; */;";
+        assert_eq!(
+            vec![
+                "CREATE FUNCTION one() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql",
+                "DO $$ BEGIN PERFORM one(); END $$",
+                "SELECT E'it\\'s;' /* a /* nested; */ comment; */",
+            ],
+            split_statements(sql, Dialect::Postgres)
+        );
+    }
+
+    #[test]
+    fn splits_mysql_delimiter_blocks() {
+        let sql = "CREATE TABLE cars(id int);\n\
+                   DELIMITER //\n\
+                   CREATE PROCEDURE add_car() BEGIN INSERT INTO cars VALUES (1); END//\n\
+                   DELIMITER ;\n\
+                   INSERT INTO cars VALUES (2); # done;\n\
+                   SELECT 'it\\'s;'";
+        assert_eq!(
+            vec![
+                "CREATE TABLE cars(id int)",
+                "CREATE PROCEDURE add_car() BEGIN INSERT INTO cars VALUES (1); END",
+                "INSERT INTO cars VALUES (2)",
+                "# done;\nSELECT 'it\\'s;'",
+            ],
+            split_statements(sql, Dialect::Mysql)
+        );
+    }
+
+    #[test]
+    fn splits_sqlite_triggers() {
+        let sql = "CREATE TEMP TRIGGER add_car AFTER INSERT ON motos BEGIN \
+                   INSERT INTO cars VALUES (CASE WHEN new.id > 1 THEN 1 ELSE 0 END); \
+                   UPDATE cars SET id = 2; END;\n\
+                   BEGIN;\nSELECT 1;\nEND;";
+        assert_eq!(
+            vec![
+                "CREATE TEMP TRIGGER add_car AFTER INSERT ON motos BEGIN \
+                 INSERT INTO cars VALUES (CASE WHEN new.id > 1 THEN 1 ELSE 0 END); \
+                 UPDATE cars SET id = 2; END",
+                "BEGIN",
+                "SELECT 1",
+                "END",
+            ],
+            split_statements(sql, Dialect::Sqlite)
+        );
+    }
+
+    #[test]
+    fn keeps_mysql_executable_comments() {
+        let sql = "/*!40101 SET NAMES utf8 */;\n/* just a comment; */;\nSELECT /*+ NO_ICP(cars) */ 1--1;\nSELECT 2 -- done";
+        assert_eq!(
+            vec!["/*!40101 SET NAMES utf8 */", "SELECT /*+ NO_ICP(cars) */ 1--1", "SELECT 2 -- done"],
+            split_statements(sql, Dialect::Mysql)
+        );
+    }
+
+    #[test]
+    fn splits_postgres_begin_atomic_bodies() {
+        let sql = "CREATE FUNCTION one() RETURNS int LANGUAGE sql BEGIN ATOMIC \
+                   SELECT CASE WHEN true THEN 1 END; SELECT 1; END;\n\
+                   BEGIN;\nSELECT 1;\nEND;";
+        assert_eq!(
+            vec![
+                "CREATE FUNCTION one() RETURNS int LANGUAGE sql BEGIN ATOMIC \
+                 SELECT CASE WHEN true THEN 1 END; SELECT 1; END",
+                "BEGIN",
+                "SELECT 1",
+                "END",
+            ],
+            split_statements(sql, Dialect::Postgres)
+        );
+    }
+}
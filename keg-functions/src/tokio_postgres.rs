@@ -0,0 +1,151 @@
+use crate::statements::numbered_placeholders;
+use crate::{
+    AppliedMigration, AppliedMigrationType, AsyncLock, AsyncMigrate, AsyncQuery, AsyncTransaction, Dialect, Param, SchemaColumn, Version,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, Utc};
+use std::time::{Duration, Instant};
+use tokio_postgres::types::{FromSql, ToSql, Type};
+use tokio_postgres::{Client, Error as PgError};
+
+// same advisory lock key as the postgres backend, so sync and async runs exclude each other
+const LOCK_KEY: i64 = 0x006b_6567;
+
+fn to_sql_params(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| -> &(dyn ToSql + Sync) {
+            match param {
+                Param::Text(text) => text,
+                Param::Int(value) => value,
+                Param::Bool(value) => value,
+                Param::Timestamp(value) => value,
+            }
+        })
+        .collect()
+}
+
+// a version that doesn't parse, inserted by hand or by another tool, fails the query instead of panicking
+impl<'a> FromSql<'a> for Version {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Version, Box<dyn std::error::Error + Sync + Send>> {
+        let text = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(text.parse()?)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+#[async_trait]
+impl AsyncTransaction for Client {
+    type Error = PgError;
+    const DIALECT: Dialect = Dialect::Postgres;
+
+    async fn execute_timed(
+        &mut self,
+        queries: &[(&str, &[Param])],
+    ) -> Result<Vec<(usize, Duration)>, (usize, Self::Error)> {
+        let transaction = self.transaction().await.map_err(|err| (queries.len(), err))?;
+        let mut executed = Vec::new();
+        for (index, (query, params)) in queries.iter().enumerate() {
+            let start = Instant::now();
+            // migration statements are executed without params and are left as is, as they may use ? as an operator
+            let result = if params.is_empty() {
+                transaction.execute(*query, &[]).await
+            } else {
+                transaction
+                    .execute(numbered_placeholders(query).as_str(), &to_sql_params(params))
+                    .await
+            };
+            let count = result.map_err(|err| (index, err))?;
+            executed.push((count as usize, start.elapsed()));
+        }
+        transaction.commit().await.map_err(|err| (queries.len(), err))?;
+        Ok(executed)
+    }
+
+    async fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)> {
+        let mut counts = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            let count = Client::execute(self, *query, &[]).await.map_err(|err| (index, err))?;
+            counts.push(count as usize);
+        }
+        Ok(counts)
+    }
+}
+
+#[async_trait]
+impl AsyncQuery<AppliedMigration> for Client {
+    async fn query(&mut self, query: &str) -> Result<Vec<AppliedMigration>, Self::Error> {
+        let transaction = self.transaction().await?;
+        let rows = transaction.query(query, &[]).await?;
+        transaction.commit().await?;
+
+        let mut applied = Vec::new();
+        for row in rows.into_iter() {
+            // repeatable migrations are recorded without version
+            let version: Version = row.try_get::<_, Option<Version>>(0)?.unwrap_or_default();
+            let installed_on: Option<DateTime<Utc>> = row.get(2);
+
+            applied.push(AppliedMigration {
+                name: row.get(1),
+                migration_type: AppliedMigrationType::from_column(row.get(5), &version),
+                description: row.get(6),
+                installed_on: installed_on.map(|installed_on| installed_on.with_timezone(&Local)),
+                version,
+                // baseline rows are recorded without checksum
+                checksum: row.get::<_, Option<String>>(3).unwrap_or_default(),
+                // rows recorded before failed migrations were tracked have no success
+                success: row.get::<_, Option<bool>>(4).unwrap_or(true),
+            });
+        }
+        Ok(applied)
+    }
+}
+
+#[async_trait]
+impl AsyncMigrate for Client {
+    async fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error> {
+        let rows = Client::query(
+            self,
+            "SELECT column_name::text, data_type::text, is_nullable::text FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = $1",
+            &[&table],
+        )
+        .await?;
+        let mut columns = Vec::new();
+        for row in rows.into_iter() {
+            let nullable: String = row.get(2);
+            columns.push(SchemaColumn {
+                name: row.get(0),
+                data_type: row.get(1),
+                nullable: nullable == "YES",
+            });
+        }
+        Ok(columns)
+    }
+}
+
+#[async_trait]
+impl AsyncLock for Client {
+    // pg_try_advisory_lock is retried until it takes the lock or timeout elapses
+    async fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        let start = Instant::now();
+        loop {
+            let row = Client::query_one(self, "SELECT pg_try_advisory_lock($1)", &[&LOCK_KEY]).await?;
+            if row.get(0) {
+                return Ok(true);
+            }
+            if start.elapsed() >= timeout {
+                return Ok(false);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn unlock(&mut self) -> Result<(), Self::Error> {
+        Client::execute(self, "SELECT pg_advisory_unlock($1)", &[&LOCK_KEY]).await?;
+        Ok(())
+    }
+}
@@ -1,14 +1,94 @@
-use chrono::Local;
-use crate::{Migration, AppliedMigration, Error, WrapMigrationError};
+use chrono::{DateTime, Local, Utc};
+#[cfg(any(feature = "rusqlite", feature = "mysql", all(feature = "async", feature = "mysql_async")))]
+use chrono::{NaiveDateTime, TimeZone};
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "rusqlite", feature = "postgres"))]
+use std::thread;
+use crate::info::migrations_info;
+use crate::statements::{split_statements, Dialect};
+use crate::{
+    Migration, MigrationInfo, MigrationReport, AppliedMigration, AppliedMigrationType, Plan, Report, SchemaColumn,
+    Error, Version, WrapMigrationError,
+};
+
+/// Value bound to a `?` placeholder of a schema history query, `None` binds `NULL`.
+/// Values are typed so that `NULL` can be bound to columns of any type
+#[derive(Clone, Debug, PartialEq)]
+pub enum Param {
+    Text(Option<String>),
+    Int(Option<i64>),
+    Bool(bool),
+    Timestamp(DateTime<Utc>),
+}
+
+impl Param {
+    fn text<T: ToString>(value: T) -> Param {
+        Param::Text(Some(value.to_string()))
+    }
+}
+
+// a schema history query and the values bound to its placeholders, in order
+pub(crate) type HistoryQuery = (&'static str, Vec<Param>);
+
+// backends without a timestamp type, or reading it as text, get installed_on in UTC without offset.
+// like parse_applied_version, text that doesn't parse is given back as the error
+#[cfg(any(feature = "rusqlite", feature = "mysql", all(feature = "async", feature = "mysql_async")))]
+pub(crate) fn parse_installed_on(installed_on: Option<String>) -> Result<Option<DateTime<Local>>, String> {
+    match installed_on {
+        Some(text) => match NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f") {
+            Ok(installed_on) => Ok(Some(Utc.from_utc_datetime(&installed_on).with_timezone(&Local))),
+            Err(_) => Err(text),
+        },
+        None => Ok(None),
+    }
+}
+
+// repeatable migrations are recorded without version. a version that doesn't parse, inserted by hand or
+// by another tool, is given back as the error so that the backend fails the query with it instead of panicking
+#[cfg(any(feature = "mysql", all(feature = "async", feature = "mysql_async")))]
+pub(crate) fn parse_applied_version(version: Option<String>) -> Result<Version, String> {
+    match version {
+        Some(text) => text.parse().map_err(|_| text),
+        None => Ok(Version::default()),
+    }
+}
+
+// borrows history queries as a batch for execute_multiple
+pub(crate) fn history_batch(queries: &[HistoryQuery]) -> Vec<(&str, &[Param])> {
+    queries.iter().map(|(query, params)| (*query, params.as_slice())).collect()
+}
 
 pub trait Transaction {
     type Error: std::error::Error + Send + Sync + 'static;
 
+    // dialect used to split migrations into statements
+    const DIALECT: Dialect = Dialect::Generic;
+
     fn execute(&mut self, query: &str) -> Result<usize, Self::Error>;
+
+    // executes the query binding params to its `?` placeholders, in order
+    fn execute_params(&mut self, query: &str, params: &[Param]) -> Result<usize, Self::Error>;
+
+    // executes the queries in a single transaction on connections of backends with transactional DDL,
+    // transactions and backends which commit DDL implicitly run them one by one
+    fn execute_batch(&mut self, queries: &[&str]) -> Result<usize, Self::Error> {
+        let mut count = 0;
+        for query in queries.iter() {
+            count += self.execute(query)?;
+        }
+        Ok(count)
+    }
 }
 
 pub trait ExecuteMultiple: Transaction {
-    fn execute_multiple(&mut self, queries: &[&str]) -> Result<usize, Self::Error>;
+    // executes the queries binding their params in a single transaction and returns the rows affected
+    // by each, errors come with the index of the failed query, or queries.len() if starting or committing
+    // the transaction failed
+    fn execute_multiple(&mut self, queries: &[(&str, &[Param])]) -> Result<Vec<usize>, (usize, Self::Error)>;
+
+    // like execute_multiple but each query is committed on its own, for migrations which can't
+    // run in a transaction
+    fn execute_no_transaction(&mut self, queries: &[&str]) -> Result<Vec<usize>, (usize, Self::Error)>;
 }
 
 pub trait CommitTransaction: Transaction
@@ -18,69 +98,880 @@ where
     fn commit(self) -> Result<(), Self::Error>;
 }
 
+// lock held while migrating so that concurrent runs wait for each other instead of racing
+// on the schema history, it's held by the connection across every transaction of a run
+pub trait Lock: Transaction {
+    // returns false if the lock couldn't be taken before timeout
+    fn lock(&mut self, timeout: Duration) -> Result<bool, Self::Error>;
+
+    // like lock, but takes over a lock taken more than stale_after ago by a run that didn't finish.
+    // Only backends whose lock outlives the connection that took it have to override it
+    fn lock_taking_over_stale(&mut self, timeout: Duration, _stale_after: Duration) -> Result<bool, Self::Error> {
+        self.lock(timeout)
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error>;
+}
+
+// retries try_lock until it takes the lock or timeout elapses
+#[cfg(any(feature = "rusqlite", feature = "postgres"))]
+pub(crate) fn poll_lock<E, F>(timeout: Duration, mut try_lock: F) -> Result<bool, E>
+where
+    F: FnMut() -> Result<bool, E>,
+{
+    let start = Instant::now();
+    loop {
+        if try_lock()? {
+            return Ok(true);
+        }
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
 pub trait Query<T>: Transaction {
-    fn query(&mut self, query: &str) -> Result<Option<T>, Self::Error>;
+    fn query(&mut self, query: &str) -> Result<Vec<T>, Self::Error>;
+}
+
+// installed_on is a native timestamp in UTC, SQLite has no date type so it's stored as text
+// in the format of its date functions
+pub(crate) fn create_schema_history_query(dialect: Dialect, table: &str) -> String {
+    let (timestamp, bigint) = match dialect {
+        Dialect::Postgres => ("TIMESTAMP WITH TIME ZONE", "BIGINT"),
+        Dialect::Mysql => ("DATETIME(6)", "BIGINT"),
+        Dialect::Sqlite => ("DATETIME", "INTEGER"),
+        Dialect::Generic => ("TIMESTAMP", "BIGINT"),
+    };
+    format!(
+        "CREATE TABLE IF NOT EXISTS {}( \
+         version VARCHAR(50) UNIQUE, \
+         name VARCHAR(255), \
+         description VARCHAR(255), \
+         type VARCHAR(20), \
+         installed_on {}, \
+         installed_by VARCHAR(100), \
+         execution_time_ms {}, \
+         checksum VARCHAR(255), \
+         success BOOLEAN)",
+        table, timestamp, bigint
+    )
+}
+
+// converts the RFC 3339 installed_on strings recorded by previous keg versions to UTC timestamps
+fn installed_on_from_text(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::Postgres => "CAST(installed_on AS TIMESTAMP WITH TIME ZONE)",
+        // MySQL can't parse the optional fraction and offset, so the fraction of a second is dropped
+        Dialect::Mysql => {
+            "CONVERT_TZ(STR_TO_DATE(LEFT(installed_on, 19), '%Y-%m-%dT%H:%i:%s'), RIGHT(installed_on, 6), '+00:00')"
+        }
+        Dialect::Sqlite => "strftime('%Y-%m-%d %H:%M:%f', installed_on)",
+        Dialect::Generic => "installed_on",
+    }
+}
+
+pub(crate) const APPLIED_MIGRATIONS_QUERY: &str =
+    "SELECT version, name, installed_on, checksum, success, type, description FROM keg_schema_history \
+     WHERE version IS NOT NULL";
+
+// repeatable migrations are recorded without version
+pub(crate) const APPLIED_REPEATABLE_MIGRATIONS_QUERY: &str =
+    "SELECT version, name, installed_on, checksum, success, type, description FROM keg_schema_history \
+     WHERE version IS NULL ORDER BY name ASC";
+
+// upgrades schema history tables created by previous keg versions. Tables with an integer version
+// as the primary key leave no place for repeatable migrations nor dotted versions, and tables with
+// a text installed_on predate native timestamps, so both are recreated with their rows copied over.
+// The columns added since are left NULL for the copied rows, as is success for rows recorded before
+// failed migrations were tracked, which are considered successful. The type is only known for baseline
+// rows, recorded without checksum
+pub(crate) fn schema_history_upgrade_queries(dialect: Dialect, columns: &[SchemaColumn]) -> Vec<String> {
+    let is_text = |c: &SchemaColumn| {
+        let data_type = c.data_type.to_lowercase();
+        data_type.contains("char") || data_type == "text"
+    };
+    let legacy_version = columns
+        .iter()
+        .any(|c| c.name == "version" && (!c.nullable || !is_text(c)));
+    let legacy_installed_on = columns.iter().any(|c| c.name == "installed_on" && is_text(c));
+    if !legacy_version && !legacy_installed_on {
+        return Vec::new();
+    }
+
+    log::debug!("upgrading keg_schema_history to record native timestamps, execution times and migration types");
+    let success = if columns.iter().any(|c| c.name == "success") {
+        "success"
+    } else {
+        "NULL"
+    };
+    let installed_on = if legacy_installed_on {
+        installed_on_from_text(dialect)
+    } else {
+        "installed_on"
+    };
+    vec![
+        create_schema_history_query(dialect, "keg_schema_history_upgrade"),
+        format!(
+            "INSERT INTO keg_schema_history_upgrade (version, name, type, installed_on, checksum, success) \
+             SELECT version, name, CASE WHEN checksum IS NULL THEN 'BASELINE' END, {}, checksum, {} \
+             FROM keg_schema_history",
+            installed_on, success
+        ),
+        "DROP TABLE keg_schema_history".to_string(),
+        "ALTER TABLE keg_schema_history_upgrade RENAME TO keg_schema_history".to_string(),
+    ]
+}
+
+// finishes an upgrade interrupted on a backend without transactional DDL, which left the upgrade table
+// behind. Once the history table is dropped the upgrade table holds every row and replaces it,
+// otherwise the upgrade table may be partial and the upgrade starts over
+pub(crate) fn interrupted_upgrade_queries(history_columns: &[SchemaColumn]) -> Vec<&'static str> {
+    if history_columns.is_empty() {
+        log::debug!("finishing interrupted upgrade of keg_schema_history");
+        vec!["ALTER TABLE keg_schema_history_upgrade RENAME TO keg_schema_history"]
+    } else {
+        log::debug!("discarding interrupted upgrade of keg_schema_history");
+        vec!["DROP TABLE keg_schema_history_upgrade"]
+    }
+}
+
+// what plan and info find of the schema history table, which they only read so that they behave the same
+// whether or not the backend commits DDL implicitly. Only runs create and upgrade it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SchemaHistoryState {
+    Missing,
+    NeedsUpgrade,
+    UpToDate,
+}
+
+pub(crate) fn schema_history_state(
+    dialect: Dialect,
+    upgrade_columns: &[SchemaColumn],
+    columns: &[SchemaColumn],
+) -> SchemaHistoryState {
+    if !upgrade_columns.is_empty() || !schema_history_upgrade_queries(dialect, columns).is_empty() {
+        SchemaHistoryState::NeedsUpgrade
+    } else if columns.is_empty() {
+        SchemaHistoryState::Missing
+    } else {
+        SchemaHistoryState::UpToDate
+    }
+}
+
+// the applied versioned and repeatable migrations
+pub(crate) type SchemaHistory = (Vec<AppliedMigration>, Vec<AppliedMigration>);
+
+// a missing schema history table has nothing applied yet
+fn get_schema_history<T>(transaction: &mut T) -> Result<Option<SchemaHistory>, Error>
+where
+    T: DefaultQueries + ?Sized,
+{
+    let upgrade_columns = transaction
+        .get_schema_history_columns("keg_schema_history_upgrade")
+        .migration_err("error getting migrations table columns")?;
+    let columns = transaction
+        .get_schema_history_columns("keg_schema_history")
+        .migration_err("error getting migrations table columns")?;
+    match schema_history_state(T::DIALECT, &upgrade_columns, &columns) {
+        SchemaHistoryState::NeedsUpgrade => Ok(None),
+        SchemaHistoryState::Missing => Ok(Some((Vec::new(), Vec::new()))),
+        SchemaHistoryState::UpToDate => {
+            let applied = transaction
+                .get_applied_migrations()
+                .migration_err("error getting applied migrations")?;
+            let applied_repeatable = transaction
+                .get_applied_repeatable_migrations()
+                .migration_err("error getting applied repeatable migrations")?;
+            Ok(Some((applied, applied_repeatable)))
+        }
+    }
+}
+
+// checksums of previous keg versions are only upgraded in memory
+pub(crate) fn plan_migrations(
+    mut applied: Vec<AppliedMigration>,
+    applied_repeatable: &[AppliedMigration],
+    migrations: &[Migration],
+    grouped: bool,
+    out_of_order: bool,
+    target: Option<&Version>,
+) -> Plan {
+    legacy_checksum_upgrades(&mut applied, migrations);
+    let pending = pending_migrations(&applied, migrations, target);
+    let repeatable = pending_repeatable_migrations(applied_repeatable, migrations, target);
+
+    let mut problems = Vec::new();
+    // the checksum of a failed migration changes once its sql is fixed, so it's only compared without failed attempts
+    if let Err(err) = verify_no_failed_migrations(applied.iter().chain(applied_repeatable)) {
+        problems.push(err);
+    } else if let Err(err) = verify_migrations(&applied, migrations) {
+        problems.push(err);
+    }
+    if let Err(err) = verify_pending_migrations(&applied, &pending, out_of_order, target) {
+        problems.push(err);
+    }
+    if grouped {
+        if let Err(err) = verify_grouped_migrations(pending.iter().chain(&repeatable).cloned()) {
+            problems.push(err);
+        }
+    }
+
+    Plan {
+        pending: pending.into_iter().chain(repeatable).cloned().collect(),
+        problems,
+    }
+}
+
+pub(crate) fn info_migrations(
+    mut applied: Vec<AppliedMigration>,
+    applied_repeatable: &[AppliedMigration],
+    migrations: &[Migration],
+) -> Vec<MigrationInfo> {
+    legacy_checksum_upgrades(&mut applied, migrations);
+    migrations_info(&applied, applied_repeatable, migrations)
+}
+
+// finishes an interrupted upgrade, creates a missing schema history table and upgrades one of a previous
+// keg version. The interrupted upgrade is checked first, as creating the history table would hide a dropped one,
+// the columns are read beforehand so that the queries run in a single batch, atomic where DDL is transactional
+pub(crate) fn assert_schema_history_queries(
+    dialect: Dialect,
+    upgrade_columns: &[SchemaColumn],
+    columns: &[SchemaColumn],
+) -> Vec<String> {
+    let mut queries = Vec::new();
+    let mut columns = columns;
+    if !upgrade_columns.is_empty() {
+        queries.extend(interrupted_upgrade_queries(columns).into_iter().map(String::from));
+        // a dropped history table is replaced by the upgrade table
+        if columns.is_empty() {
+            columns = upgrade_columns;
+        }
+    }
+    queries.push(create_schema_history_query(dialect, "keg_schema_history"));
+    queries.extend(schema_history_upgrade_queries(dialect, columns));
+    queries
 }
 
 pub trait DefaultQueries: Transaction + Query<AppliedMigration> {
+    // columns of the given schema history table, empty if it doesn't exist
+    fn get_schema_history_columns(&mut self, table: &str) -> Result<Vec<SchemaColumn>, Self::Error>;
+
     fn assert_schema_history_table(&mut self) -> Result<usize, Self::Error> {
-        self.execute(
-            "CREATE TABLE IF NOT EXISTS keg_schema_history( \
-                 version INTEGER PRIMARY KEY,\
-                 name VARCHAR(255),\
-                 installed_on VARCHAR(255),
-                 checksum VARCHAR(255));",
-        )
+        let upgrade_columns = self.get_schema_history_columns("keg_schema_history_upgrade")?;
+        let columns = self.get_schema_history_columns("keg_schema_history")?;
+        let queries = assert_schema_history_queries(Self::DIALECT, &upgrade_columns, &columns);
+        self.execute_batch(&queries.iter().map(String::as_str).collect::<Vec<_>>())
     }
 
     fn get_current_version(&mut self) -> Result<Option<AppliedMigration>, Self::Error> {
-        self.query(
-            "SELECT version, name, installed_on, checksum FROM keg_schema_history where version=(SELECT MAX(version) from keg_schema_history)",
-        )
+        self.get_applied_migrations().map(|mut applied| applied.pop())
+    }
+
+    // versions are stored as text, so they are sorted here instead of with ORDER BY
+    fn get_applied_migrations(&mut self) -> Result<Vec<AppliedMigration>, Self::Error> {
+        self.query(APPLIED_MIGRATIONS_QUERY).map(|mut applied| {
+            applied.sort_by(|a, b| a.version.cmp(&b.version));
+            applied
+        })
+    }
+
+    fn get_applied_repeatable_migrations(&mut self) -> Result<Vec<AppliedMigration>, Self::Error> {
+        self.query(APPLIED_REPEATABLE_MIGRATIONS_QUERY)
+    }
+}
+
+// rewrites the checksums stored by previous keg versions with the stable ones, returning the queries
+// to update the schema history. rows that don't match the legacy checksum are left as is for verify_migrations to report
+pub(crate) fn legacy_checksum_upgrades<'m>(
+    applied: &mut [AppliedMigration],
+    migrations: &'m [Migration],
+) -> Vec<(&'m Migration, HistoryQuery)> {
+    let mut upgrades = Vec::new();
+    for applied in applied.iter_mut() {
+        let migration = migrations
+            .iter()
+            .find(|m| m.is_versioned() && m.version == applied.version);
+        if let Some(migration) = migration {
+            if migration.legacy_checksum().map(|c| c.to_string()).as_ref() == Some(&applied.checksum) {
+                log::debug!("upgrading checksum of migration: {}", migration);
+                let checksum = migration.checksum().to_string();
+                upgrades.push((
+                    migration,
+                    (
+                        "UPDATE keg_schema_history SET checksum = ? WHERE version = ?",
+                        vec![Param::text(&checksum), Param::text(&applied.version)],
+                    ),
+                ));
+                applied.checksum = checksum;
+            }
+        }
+    }
+    upgrades
+}
+
+fn upgrade_legacy_checksums<T>(
+    transaction: &mut T,
+    mut applied: Vec<AppliedMigration>,
+    migrations: &[Migration],
+) -> Result<Vec<AppliedMigration>, Error>
+where
+    T: Transaction + ?Sized,
+{
+    for (migration, (query, params)) in legacy_checksum_upgrades(&mut applied, migrations) {
+        transaction
+            .execute_params(query, &params)
+            .migration_err(&format!("error upgrading checksum of migration {}", migration))?;
+    }
+    Ok(applied)
+}
+
+// compares the applied migrations with the local ones of the same version
+// and returns every one whose name or checksum differ
+pub(crate) fn verify_migrations(applied: &[AppliedMigration], migrations: &[Migration]) -> Result<(), Error> {
+    let mut divergent = Vec::new();
+    for applied in applied.iter().filter(|a| !a.is_baseline()) {
+        let migration = migrations
+            .iter()
+            .find(|m| m.is_versioned() && m.version == applied.version);
+        if let Some(migration) = migration {
+            if migration.name != applied.name || migration.checksum().to_string() != applied.checksum {
+                divergent.push((applied.clone(), migration.clone()));
+            }
+        }
+    }
+
+    if divergent.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::DivergentVersions(divergent))
+    }
+}
+
+// versioned migrations not yet applied up to target,
+// migrations up to the baseline, if any, are considered applied
+pub(crate) fn pending_migrations<'m>(
+    applied: &[AppliedMigration],
+    migrations: &'m [Migration],
+    target: Option<&Version>,
+) -> Vec<&'m Migration> {
+    let baseline = applied.iter().filter(|a| a.is_baseline()).map(|a| &a.version).max();
+    for applied in applied.iter().filter(|a| !a.is_baseline()) {
+        if !migrations.iter().any(|m| m.is_versioned() && m.version == applied.version) {
+            log::warn!("applied migration V{}__{} is missing locally", applied.version, applied.name);
+        }
+    }
+
+    let mut pending = migrations
+        .iter()
+        .filter(|m| m.is_versioned() && !applied.iter().any(|a| a.version == m.version))
+        .filter(|m| match baseline {
+            Some(baseline) => m.version > *baseline,
+            None => true,
+        })
+        .filter(|m| match target {
+            Some(target) => m.version <= *target,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    pending.sort();
+    pending
+}
+
+// pending migrations with a version lower than the current one were probably merged
+// after a later migration was deployed and are only applied when out_of_order is set
+pub(crate) fn verify_pending_migrations(
+    applied: &[AppliedMigration],
+    pending: &[&Migration],
+    out_of_order: bool,
+    target: Option<&Version>,
+) -> Result<(), Error> {
+    if let Some(current) = applied.iter().map(|a| &a.version).max() {
+        log::debug!("current migration: {}", current);
+        if let Some(target) = target {
+            if target < current {
+                return Err(Error::TargetVersionBelowCurrent(target.clone(), current.clone()));
+            }
+        }
+        let lower = pending
+            .iter()
+            .filter(|m| m.version < *current)
+            .map(|m| (*m).clone())
+            .collect::<Vec<_>>();
+        if !lower.is_empty() && !out_of_order {
+            return Err(Error::OutOfOrderMigrations(lower));
+        }
+    }
+    Ok(())
+}
+
+// repeatable migrations which were never applied or whose checksum changed since. They are written
+// against the latest schema, so none are pending while a target below the latest version holds it back
+pub(crate) fn pending_repeatable_migrations<'m>(
+    applied: &[AppliedMigration],
+    migrations: &'m [Migration],
+    target: Option<&Version>,
+) -> Vec<&'m Migration> {
+    let latest = migrations.iter().filter(|m| m.is_versioned()).map(|m| &m.version).max();
+    if let (Some(target), Some(latest)) = (target, latest) {
+        if target < latest {
+            log::debug!("skipping repeatable migrations, target {} is below the latest version {}", target, latest);
+            return Vec::new();
+        }
+    }
+    let mut migrations = migrations
+        .iter()
+        .filter(|m| m.is_repeatable())
+        .filter(|m| {
+            !applied
+                .iter()
+                .any(|a| a.name == m.name && a.checksum == m.checksum().to_string())
+        })
+        .collect::<Vec<_>>();
+    migrations.sort();
+    migrations
+}
+
+// installed_by is the database user running the migrations, SQLite has no users
+fn insert_history_query(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::Sqlite => {
+            "INSERT INTO keg_schema_history (version, name, description, type, installed_on, installed_by, \
+             execution_time_ms, checksum, success) VALUES (?, ?, ?, ?, ?, NULL, ?, ?, ?)"
+        }
+        _ => {
+            "INSERT INTO keg_schema_history (version, name, description, type, installed_on, installed_by, \
+             execution_time_ms, checksum, success) VALUES (?, ?, ?, ?, ?, CURRENT_USER, ?, ?, ?)"
+        }
+    }
+}
+
+fn execution_time_param(execution_time: Option<Duration>) -> Param {
+    Param::Int(execution_time.map(|duration| duration.as_millis() as i64))
+}
+
+// the execution time is only known when the history is updated after the migration ran in the same
+// transaction, otherwise it's recorded afterwards with execution_time_query
+pub(crate) fn history_query(
+    dialect: Dialect,
+    migration: &Migration,
+    success: bool,
+    execution_time: Option<Duration>,
+) -> HistoryQuery {
+    (
+        insert_history_query(dialect),
+        vec![
+            // repeatable migrations are recorded without version
+            Param::Text(migration.version().map(ToString::to_string)),
+            Param::text(&migration.name),
+            Param::Text(migration.description().map(ToString::to_string)),
+            Param::text(AppliedMigrationType::of(migration).as_str()),
+            Param::Timestamp(Utc::now()),
+            execution_time_param(execution_time),
+            Param::text(migration.checksum()),
+            Param::Bool(success),
+        ],
+    )
+}
+
+pub(crate) fn repeatable_history_queries(
+    dialect: Dialect,
+    migration: &Migration,
+    execution_time: Option<Duration>,
+) -> Vec<HistoryQuery> {
+    vec![
+        (
+            "DELETE FROM keg_schema_history WHERE version IS NULL AND name = ?",
+            vec![Param::text(&migration.name)],
+        ),
+        history_query(dialect, migration, true, execution_time),
+    ]
+}
+
+pub(crate) fn execution_time_query(migration: &Migration, execution_time: Duration) -> HistoryQuery {
+    match migration.version() {
+        Some(version) => (
+            "UPDATE keg_schema_history SET execution_time_ms = ? WHERE version = ?",
+            vec![execution_time_param(Some(execution_time)), Param::text(version)],
+        ),
+        None => (
+            "UPDATE keg_schema_history SET execution_time_ms = ? WHERE version IS NULL AND name = ?",
+            vec![execution_time_param(Some(execution_time)), Param::text(&migration.name)],
+        ),
+    }
+}
+
+// baselines are recorded without checksum, the description doubles as their name
+pub(crate) fn baseline_query(dialect: Dialect, version: &Version, description: &str) -> HistoryQuery {
+    (
+        insert_history_query(dialect),
+        vec![
+            Param::text(version),
+            Param::text(description),
+            Param::text(description),
+            Param::text(AppliedMigrationType::Baseline.as_str()),
+            Param::Timestamp(Utc::now()),
+            Param::Int(None),
+            Param::Text(None),
+            Param::Bool(true),
+        ],
+    )
+}
+
+pub(crate) fn delete_history_query(version: &Version) -> HistoryQuery {
+    (
+        "DELETE FROM keg_schema_history WHERE version = ?",
+        vec![Param::text(version)],
+    )
+}
+
+// migrations whose last attempt failed, the schema may have been left half migrated
+// so nothing else runs until they are repaired
+pub(crate) fn verify_no_failed_migrations<'m, I>(applied: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = &'m AppliedMigration>,
+{
+    let failed = applied
+        .into_iter()
+        .filter(|a| !a.success)
+        .cloned()
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::FailedMigrations(failed))
+    }
+}
+
+// error of the query at index of a migration batch, made of the migration statements followed by the
+// schema history queries, naming the failed statement so that it can be found in long scripts
+pub(crate) fn migration_batch_error<E>(migration: &Migration, statements: &[&str], history_len: usize, index: usize, err: E) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let msg = if index < statements.len() {
+        format!("error applying migration {}, statement {}: {}", migration, index + 1, statements[index])
+    } else if index < statements.len() + history_len {
+        format!("error updating schema history to migration: {}", migration)
+    } else {
+        format!("error applying migration {}", migration)
+    };
+    Error::TransactionError(msg, Box::new(err))
+}
+
+// executes the migration statements one by one, returning the rows affected by all of them
+fn execute_statements<T>(transaction: &mut T, migration: &Migration) -> Result<usize, Error>
+where
+    T: Transaction + ?Sized,
+{
+    let statements = split_statements(&migration.sql, T::DIALECT);
+    let mut rows_affected = 0;
+    for (index, statement) in statements.iter().enumerate() {
+        rows_affected += transaction
+            .execute(statement)
+            .map_err(|err| migration_batch_error(migration, &statements, 0, index, err))?;
+    }
+    Ok(rows_affected)
+}
+
+// executes the migration statements followed by the schema history queries in a single transaction,
+// returning the rows affected by the migration. Migrations which can't run in a transaction are
+// executed statement by statement and the history is only updated once all of them succeed
+fn execute_migration<T>(conn: &mut T, migration: &Migration, history_queries: &[HistoryQuery]) -> Result<usize, Error>
+where
+    T: ExecuteMultiple + ?Sized,
+{
+    let statements = split_statements(&migration.sql, T::DIALECT);
+    if migration.no_transaction() {
+        let rows_affected = conn
+            .execute_no_transaction(&statements)
+            .map_err(|(index, err)| migration_batch_error(migration, &statements, 0, index, err))?;
+        conn.execute_multiple(&history_batch(history_queries))
+            .map_err(|(index, err)| migration_batch_error(migration, &[], history_queries.len(), index, err))?;
+        return Ok(rows_affected.iter().sum());
+    }
+
+    let queries = statements
+        .iter()
+        .map(|statement| (*statement, &[] as &[Param]))
+        .chain(history_batch(history_queries))
+        .collect::<Vec<_>>();
+    match conn.execute_multiple(&queries) {
+        Ok(rows_affected) => Ok(rows_affected[..statements.len()].iter().sum()),
+        Err((index, err)) => Err(migration_batch_error(migration, &statements, history_queries.len(), index, err)),
+    }
+}
+
+// everything runs in a single transaction when grouped, so migrations which can't run in one are refused
+pub(crate) fn verify_grouped_migrations<'m, I>(migrations: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = &'m Migration>,
+{
+    let no_transaction = migrations
+        .into_iter()
+        .filter(|m| m.no_transaction())
+        .cloned()
+        .collect::<Vec<_>>();
+    if no_transaction.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::NoTransactionMigrationsGrouped(no_transaction))
+    }
+}
+
+// a migration rolled back as a whole can be fixed and run again, only one which may have been
+// partially applied needs a repair. that's the case when it runs outside of a transaction,
+// or on MySQL where DDL statements commit implicitly
+pub(crate) fn may_be_partially_applied(dialect: Dialect, migration: &Migration) -> bool {
+    migration.no_transaction() || dialect == Dialect::Mysql
+}
+
+// records a failed attempt that may have been partially applied so that the next run refuses
+// to proceed until it's repaired, failing to do so is only logged as the migration error is the one to report
+fn record_failed_migration<T>(transaction: &mut T, migration: &Migration)
+where
+    T: Transaction + ?Sized,
+{
+    if !may_be_partially_applied(T::DIALECT, migration) {
+        return;
+    }
+    let (query, params) = history_query(T::DIALECT, migration, false, None);
+    if let Err(err) = transaction.execute_params(query, &params) {
+        log::warn!("error recording failed migration {}: {}", migration, err);
+    }
+}
+
+// the execution time of a migration applied in its own transaction is only known once it's committed,
+// failing to record it is only logged as the migration is applied
+fn record_execution_time<T>(transaction: &mut T, migration: &Migration, execution_time: Duration)
+where
+    T: Transaction + ?Sized,
+{
+    let (query, params) = execution_time_query(migration, execution_time);
+    if let Err(err) = transaction.execute_params(query, &params) {
+        log::warn!("error recording execution time of migration {}: {}", migration, err);
     }
 }
 
+// deletes failed attempts and aligns the name and checksum of applied migrations with the local ones
+pub(crate) fn repair_queries(applied: &[AppliedMigration], migrations: &[Migration]) -> Vec<HistoryQuery> {
+    let mut queries = vec![(
+        "DELETE FROM keg_schema_history WHERE success = ?",
+        vec![Param::Bool(false)],
+    )];
+    for applied in applied.iter().filter(|a| a.success && !a.is_baseline()) {
+        let migration = migrations
+            .iter()
+            .find(|m| m.is_versioned() && m.version == applied.version);
+        if let Some(migration) = migration {
+            if migration.name != applied.name || migration.checksum().to_string() != applied.checksum {
+                log::debug!("realigning applied migration V{}__{} with {}", applied.version, applied.name, migration);
+                queries.push((
+                    "UPDATE keg_schema_history SET name = ?, checksum = ? WHERE version = ?",
+                    vec![
+                        Param::text(&migration.name),
+                        Param::text(migration.checksum()),
+                        Param::text(&applied.version),
+                    ],
+                ));
+            }
+        }
+    }
+    queries
+}
+
+// undo migrations for every applied migration above target, in the order they must be applied
+pub(crate) fn pending_undo_migrations<'m>(
+    applied: &'m [AppliedMigration],
+    migrations: &'m [Migration],
+    target: &Version,
+) -> Result<Vec<(&'m AppliedMigration, &'m Migration)>, Error> {
+    let mut undo = Vec::new();
+    for applied in applied.iter().rev().filter(|a| a.version > *target) {
+        let migration = migrations
+            .iter()
+            .find(|m| m.is_undo() && m.version == applied.version)
+            .ok_or_else(|| Error::MissingUndoMigration(Box::new(applied.clone())))?;
+        undo.push((applied, migration));
+    }
+    Ok(undo)
+}
+
 pub trait MigrateGrouped<'a> {
     type Transaction: DefaultQueries + CommitTransaction;
 
-    fn migrate(&'a mut self, migrations: &[Migration]) -> Result<(), Error> {
+    fn migrate(&'a mut self, migrations: &[Migration], out_of_order: bool, target: Option<&Version>) -> Result<Report, Error> {
         let mut transaction = self.transaction()?;
         transaction
             .assert_schema_history_table()
             .migration_err("error asserting migrations table")?;
 
-        let current = transaction
-            .get_current_version()
-            .migration_err("error getting current schema version")?
-            .unwrap_or(AppliedMigration {
-                name: "".into(),
-                version: 0,
-                installed_on: Local::now(),
-                checksum: "".into(),
-            });
-        log::debug!("current migration: {}", current.version);
-
-        let mut migrations = migrations
-            .iter()
-            .filter(|m| m.version > current.version)
-            .collect::<Vec<_>>();
-        migrations.sort();
+        let applied = transaction
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(&mut transaction, applied, migrations)?;
+        let applied_repeatable = transaction
+            .get_applied_repeatable_migrations()
+            .migration_err("error getting applied repeatable migrations")?;
+        // failed attempts come first, as fixing the sql of a failed migration changes its checksum
+        verify_no_failed_migrations(applied.iter().chain(&applied_repeatable))?;
+        verify_migrations(&applied, migrations)?;
+        let pending = pending_migrations(&applied, migrations, target);
+        verify_pending_migrations(&applied, &pending, out_of_order, target)?;
+        let repeatable = pending_repeatable_migrations(&applied_repeatable, migrations, target);
+        verify_grouped_migrations(pending.iter().chain(&repeatable).cloned())?;
 
-        if migrations.is_empty() {
+        if pending.is_empty() && repeatable.is_empty() {
             log::debug!("no migrations to apply");
         }
 
-        for migration in migrations.iter() {
+        let mut report = Report::default();
+        for migration in pending.iter() {
             log::debug!("applying migration: {}", migration.name);
+            let started_on = Local::now();
+            let start = Instant::now();
+            let rows_affected = execute_statements(&mut transaction, migration)?;
+            let duration = start.elapsed();
+            report.push(MigrationReport::new(migration, started_on, duration, rows_affected));
+
+            let (query, params) = history_query(Self::Transaction::DIALECT, migration, true, Some(duration));
+            transaction
+                .execute_params(query, &params)
+                .migration_err(&format!("error updating schema history to migration: {}", migration))?;
+        }
+
+        for migration in repeatable.iter() {
+            log::debug!("applying repeatable migration: {}", migration.name);
+            let started_on = Local::now();
+            let start = Instant::now();
+            let rows_affected = execute_statements(&mut transaction, migration)?;
+            let duration = start.elapsed();
+            report.push(MigrationReport::new(migration, started_on, duration, rows_affected));
+
+            for (query, params) in repeatable_history_queries(Self::Transaction::DIALECT, migration, Some(duration)) {
+                transaction
+                    .execute_params(query, &params)
+                    .migration_err(&format!("error updating schema history to migration: {}", migration))?;
+            }
+        }
+
+        transaction
+            .commit()
+            .migration_err("error committing transaction")?;
+
+        Ok(report)
+    }
+
+    // the schema history is only read, a missing table leaves every migration pending. A table of a previous
+    // keg version can't be read before the run upgrading it, so it's only reported
+    fn plan(
+        &'a mut self,
+        migrations: &[Migration],
+        grouped: bool,
+        out_of_order: bool,
+        target: Option<&Version>,
+    ) -> Result<Plan, Error> {
+        let mut transaction = self.transaction()?;
+        match get_schema_history(&mut transaction)? {
+            Some((applied, applied_repeatable)) => Ok(plan_migrations(
+                applied,
+                &applied_repeatable,
+                migrations,
+                grouped,
+                out_of_order,
+                target,
+            )),
+            None => Ok(Plan {
+                pending: Vec::new(),
+                problems: vec![Error::SchemaHistoryNeedsUpgrade],
+            }),
+        }
+    }
+
+    // like plan, only reads the schema history and fails if it has to be upgraded
+    fn info(&'a mut self, migrations: &[Migration]) -> Result<Vec<MigrationInfo>, Error> {
+        let mut transaction = self.transaction()?;
+        match get_schema_history(&mut transaction)? {
+            Some((applied, applied_repeatable)) => Ok(info_migrations(applied, &applied_repeatable, migrations)),
+            None => Err(Error::SchemaHistoryNeedsUpgrade),
+        }
+    }
+
+    fn baseline(&'a mut self, version: &Version, description: &str) -> Result<(), Error> {
+        let mut transaction = self.transaction()?;
+        transaction
+            .assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = transaction
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied_repeatable = transaction
+            .get_applied_repeatable_migrations()
+            .migration_err("error getting applied repeatable migrations")?;
+        if !applied.is_empty() || !applied_repeatable.is_empty() {
+            return Err(Error::SchemaHistoryNotEmpty);
+        }
+
+        log::debug!("baselining schema history at version: {}", version);
+        let (query, params) = baseline_query(Self::Transaction::DIALECT, version, description);
+        transaction
+            .execute_params(query, &params)
+            .migration_err("error updating schema history to baseline")?;
+
+        transaction
+            .commit()
+            .migration_err("error committing transaction")?;
+
+        Ok(())
+    }
+
+    fn repair(&'a mut self, migrations: &[Migration]) -> Result<(), Error> {
+        let mut transaction = self.transaction()?;
+        transaction
+            .assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = transaction
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        for (query, params) in repair_queries(&applied, migrations) {
             transaction
-                .execute(&migration.sql)
-                .migration_err(&format!("error applying migration {}", migration))?;
+                .execute_params(query, &params)
+                .migration_err("error repairing schema history")?;
+        }
+
+        transaction
+            .commit()
+            .migration_err("error committing transaction")?;
+
+        Ok(())
+    }
+
+    fn rollback(&'a mut self, migrations: &[Migration], target: &Version) -> Result<(), Error> {
+        let mut transaction = self.transaction()?;
+        transaction
+            .assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = transaction
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(&mut transaction, applied, migrations)?;
+        verify_no_failed_migrations(&applied)?;
+        verify_migrations(&applied, migrations)?;
+        let undo = pending_undo_migrations(&applied, migrations, target)?;
+        verify_grouped_migrations(undo.iter().map(|(_, migration)| *migration))?;
 
+        for (applied, migration) in undo {
+            log::debug!("reverting migration: {}", migration.name);
+            execute_statements(&mut transaction, migration)?;
+
+            let (query, params) = delete_history_query(&applied.version);
             transaction
-                .execute(&format!(
-                    "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES ({}, '{}', '{}', '{}')",
-                    migration.version, migration.name, Local::now().to_rfc3339(), migration.checksum().to_string()
-                ))
+                .execute_params(query, &params)
                 .migration_err(&format!("error updating schema history to migration: {}", migration))?;
         }
 
@@ -95,32 +986,113 @@ pub trait MigrateGrouped<'a> {
 }
 
 pub trait Migrate: DefaultQueries + ExecuteMultiple {
-    fn migrate(&mut self, migrations: &[Migration]) -> Result<(), Error> {
+    // migrations are applied and committed one by one, so when one fails the error
+    // carries the report of the ones applied before it
+    fn migrate(&mut self, migrations: &[Migration], out_of_order: bool, target: Option<&Version>) -> Result<Report, Error> {
         self.assert_schema_history_table()
             .migration_err("error asserting migrations table")?;
-        let current = self
-            .get_current_version()
-            .migration_err("error getting current schema version")?
-            .unwrap_or(AppliedMigration {
-                name: "".into(),
-                version: 0,
-                installed_on: Local::now(),
-                checksum: "".into(),
-            });
-
-        log::debug!("current migration: {}", current.version);
-        let mut migrations = migrations
-            .iter()
-            .filter(|m| m.version > current.version)
-            .collect::<Vec<_>>();
-        migrations.sort();
-
-        for migration in migrations.iter() {
-            let update_query = &format!(
-                "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES ({}, '{}', '{}', '{}')",
-                migration.version, migration.name, Local::now().to_rfc3339(), migration.checksum().to_string());
-            self.execute_multiple(&[&migration.sql, update_query])
-                .migration_err(&format!("error applying migration {}", migration))?;
+
+        let applied = self
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(self, applied, migrations)?;
+        let applied_repeatable = self
+            .get_applied_repeatable_migrations()
+            .migration_err("error getting applied repeatable migrations")?;
+        // failed attempts come first, as fixing the sql of a failed migration changes its checksum
+        verify_no_failed_migrations(applied.iter().chain(&applied_repeatable))?;
+        verify_migrations(&applied, migrations)?;
+        let pending = pending_migrations(&applied, migrations, target);
+        verify_pending_migrations(&applied, &pending, out_of_order, target)?;
+        let repeatable = pending_repeatable_migrations(&applied_repeatable, migrations, target);
+
+        let mut report = Report::default();
+        for migration in pending.iter() {
+            let history_queries = [history_query(Self::DIALECT, migration, true, None)];
+            let started_on = Local::now();
+            let start = Instant::now();
+            let rows_affected = match execute_migration(self, migration, &history_queries) {
+                Ok(rows_affected) => rows_affected,
+                Err(err) => {
+                    record_failed_migration(self, migration);
+                    return Err(Error::RunAborted(Box::new(err), report));
+                }
+            };
+            let duration = start.elapsed();
+            record_execution_time(self, migration, duration);
+            report.push(MigrationReport::new(migration, started_on, duration, rows_affected));
+        }
+
+        for migration in repeatable.iter() {
+            let history_queries = repeatable_history_queries(Self::DIALECT, migration, None);
+            let started_on = Local::now();
+            let start = Instant::now();
+            let rows_affected = match execute_migration(self, migration, &history_queries) {
+                Ok(rows_affected) => rows_affected,
+                Err(err) => {
+                    record_failed_migration(self, migration);
+                    return Err(Error::RunAborted(Box::new(err), report));
+                }
+            };
+            let duration = start.elapsed();
+            record_execution_time(self, migration, duration);
+            report.push(MigrationReport::new(migration, started_on, duration, rows_affected));
+        }
+
+        Ok(report)
+    }
+
+    fn baseline(&mut self, version: &Version, description: &str) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied_repeatable = self
+            .get_applied_repeatable_migrations()
+            .migration_err("error getting applied repeatable migrations")?;
+        if !applied.is_empty() || !applied_repeatable.is_empty() {
+            return Err(Error::SchemaHistoryNotEmpty);
+        }
+
+        log::debug!("baselining schema history at version: {}", version);
+        let (query, params) = baseline_query(Self::DIALECT, version, description);
+        self.execute_params(query, &params)
+            .migration_err("error updating schema history to baseline")?;
+
+        Ok(())
+    }
+
+    fn repair(&mut self, migrations: &[Migration]) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let queries = repair_queries(&applied, migrations);
+        self.execute_multiple(&history_batch(&queries))
+            .map_err(|(_, err)| err)
+            .migration_err("error repairing schema history")?;
+
+        Ok(())
+    }
+
+    fn rollback(&mut self, migrations: &[Migration], target: &Version) -> Result<(), Error> {
+        self.assert_schema_history_table()
+            .migration_err("error asserting migrations table")?;
+
+        let applied = self
+            .get_applied_migrations()
+            .migration_err("error getting applied migrations")?;
+        let applied = upgrade_legacy_checksums(self, applied, migrations)?;
+        verify_no_failed_migrations(&applied)?;
+        verify_migrations(&applied, migrations)?;
+
+        for (applied, migration) in pending_undo_migrations(&applied, migrations, target)? {
+            log::debug!("reverting migration: {}", migration.name);
+            execute_migration(self, migration, &[delete_history_query(&applied.version)])?;
         }
 
         Ok(())
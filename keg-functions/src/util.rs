@@ -0,0 +1,242 @@
+use crate::{Error, Migration, Version};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// Kind of a migration file, sql migrations are embedded and mod migrations are Rust modules
+/// returning the sql
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationType {
+    Mod,
+    Sql,
+}
+
+// sorts by version then name so that the discovery order doesn't depend on the filesystem,
+// the path breaks ties between files with the same name in different dirs
+fn sort_key(path: &Path) -> (Option<Version>, String, PathBuf) {
+    let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+    let (version, name) = match crate::RE.captures(stem) {
+        Some(captures) => (captures.get(2).and_then(|v| v.as_str().parse().ok()), captures[3].to_string()),
+        None => (None, stem.to_string()),
+    };
+    (version, name, path.to_path_buf())
+}
+
+/// Finds the files under `location` whose file stem matches the migration regex and have the extension
+/// of `migration_type`, sorted by version and name
+pub fn find_migration_files(location: &Path, migration_type: MigrationType) -> Result<Vec<PathBuf>, Error> {
+    let start = fs::canonicalize(location).map_err(|err| Error::InvalidMigrationPath(location.to_path_buf(), err))?;
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&start) {
+        let entry = entry.map_err(|err| {
+            let path = err.path().unwrap_or(&start).to_path_buf();
+            Error::InvalidMigrationPath(path, io::Error::from(err))
+        })?;
+        files.push(DirEntry::into_path(entry));
+    }
+
+    let mut files = files
+        .into_iter()
+        //filter entries which match the valid migration regex, only the file stem is matched
+        //so that the name of a parent dir can't make every file below it a migration
+        .filter(|entry| {
+            entry
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .filter(|stem| crate::RE.is_match(stem))
+                .is_some()
+        })
+        //match the right extension
+        .filter(|entry| {
+            entry
+                .extension()
+                .and_then(OsStr::to_str)
+                .filter(|ext| match migration_type {
+                    MigrationType::Mod => ext == &"rs",
+                    MigrationType::Sql => ext == &"sql",
+                })
+                .is_some()
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_cached_key(|path| sort_key(path));
+    Ok(files)
+}
+
+/// Parses migration files given as their path and sql, the name is the file stem. Invalid names and
+/// versions shared by two files fail, as the second one would only fail once applied
+pub fn parse_migration_files<P: AsRef<Path>>(files: &[(P, String)]) -> Result<Vec<Migration>, Error> {
+    let mut parsed: Vec<(&Path, Migration)> = Vec::new();
+    for (path, sql) in files.iter() {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .ok_or_else(|| Error::InvalidMigrationFile(path.to_path_buf(), Box::new(Error::InvalidName)))?;
+        let migration = Migration::from_filename(name, sql)
+            .map_err(|err| Error::InvalidMigrationFile(path.to_path_buf(), Box::new(err)))?;
+        if let Some((other, _)) = parsed.iter().find(|(_, other)| other == &migration) {
+            return Err(Error::DuplicateMigrationVersion(other.to_path_buf(), path.to_path_buf()));
+        }
+        parsed.push((path, migration));
+    }
+    Ok(parsed.into_iter().map(|(_, migration)| migration).collect())
+}
+
+/// Loads the sql migrations under `location` at runtime, as `embed_migrations!` does at compile time,
+/// so that they can be shipped separately from the binary. Migrations are sorted by version
+pub fn load_sql_migrations(location: &Path) -> Result<Vec<Migration>, Error> {
+    let mut files = Vec::new();
+    for path in find_migration_files(location, MigrationType::Sql)? {
+        let sql = fs::read_to_string(&path).map_err(|err| Error::InvalidMigrationPath(path.clone(), err))?;
+        files.push((path, sql));
+    }
+    let mut migrations = parse_migration_files(&files)?;
+    migrations.sort();
+    Ok(migrations)
+}
+
+// location and every dir below it, adding or removing a file changes the dir it's in
+fn migration_dirs(location: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs = Vec::new();
+    for entry in WalkDir::new(location) {
+        let entry = entry.map_err(|err| {
+            let path = err.path().unwrap_or(location).to_path_buf();
+            Error::InvalidMigrationPath(path, io::Error::from(err))
+        })?;
+        if entry.file_type().is_dir() {
+            dirs.push(DirEntry::into_path(entry));
+        }
+    }
+    Ok(dirs)
+}
+
+/// Makes cargo rebuild the crate when migrations under `location` are added or removed, which
+/// `embed_migrations!` and `include_migration_mods!` can't track as they only see the files present when
+/// they ran. Call it from the crate's build script with the location given to the macro, e.g.
+/// `keg::rerun_if_migrations_changed(Path::new("migrations")).unwrap();` in `build.rs`
+pub fn rerun_if_migrations_changed(location: &Path) -> Result<(), Error> {
+    for dir in migration_dirs(location)? {
+        println!("cargo:rerun-if-changed={}", dir.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_migration_files, load_sql_migrations, migration_dirs, MigrationType};
+    use crate::Error;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn loads_sql_migrations_sorted_by_version() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        fs::create_dir(tmp_dir.path().join("nested")).unwrap();
+        fs::write(tmp_dir.path().join("V2__second.sql"), "SELECT 2").unwrap();
+        fs::write(tmp_dir.path().join("nested/V1__first.sql"), "SELECT 1").unwrap();
+        fs::write(tmp_dir.path().join("V3__third.rs"), "").unwrap();
+        fs::write(tmp_dir.path().join("README.sql"), "").unwrap();
+
+        let migrations = load_sql_migrations(tmp_dir.path()).unwrap();
+        let names = migrations.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["V1__first", "V2__second"], names);
+    }
+
+    #[test]
+    fn finds_migration_files_sorted_by_version_and_name() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        fs::create_dir(tmp_dir.path().join("nested")).unwrap();
+        fs::write(tmp_dir.path().join("V10__tenth.sql"), "").unwrap();
+        fs::write(tmp_dir.path().join("nested/V2__second.sql"), "").unwrap();
+        fs::write(tmp_dir.path().join("V2__first.sql"), "").unwrap();
+        fs::write(tmp_dir.path().join("V1.1__patch.sql"), "").unwrap();
+
+        let files = find_migration_files(tmp_dir.path(), MigrationType::Sql).unwrap();
+        let names = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["V1.1__patch.sql", "V2__first.sql", "V2__second.sql", "V10__tenth.sql"],
+            names
+        );
+    }
+
+    #[test]
+    fn fails_loading_duplicate_versions() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        fs::write(tmp_dir.path().join("V2__a.sql"), "SELECT 1").unwrap();
+        fs::write(tmp_dir.path().join("V2__b.sql"), "SELECT 2").unwrap();
+
+        match load_sql_migrations(tmp_dir.path()) {
+            Err(Error::DuplicateMigrationVersion(path, other)) => {
+                assert!(path.ends_with("V2__a.sql"));
+                assert!(other.ends_with("V2__b.sql"));
+            }
+            _ => panic!("expected duplicate migration version error"),
+        }
+    }
+
+    #[test]
+    fn ignores_files_under_dirs_named_like_migrations() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        let location = tmp_dir.path().join("USER__x");
+        fs::create_dir(&location).unwrap();
+        fs::write(location.join("V1__first.sql"), "SELECT 1").unwrap();
+        fs::write(location.join("README.sql"), "").unwrap();
+
+        let migrations = load_sql_migrations(&location).unwrap();
+        let names = migrations.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["V1__first"], names);
+    }
+
+    #[test]
+    fn fails_loading_missing_directory() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        let location = tmp_dir.path().join("missing");
+
+        match load_sql_migrations(&location) {
+            Err(Error::InvalidMigrationPath(path, _)) => assert_eq!(location, path),
+            _ => panic!("expected invalid migration path error"),
+        }
+    }
+
+    #[test]
+    fn fails_loading_invalid_migration_name() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        fs::write(tmp_dir.path().join("V1__first.sql"), "SELECT 1").unwrap();
+        fs::write(tmp_dir.path().join("R1__repeatable.sql"), "SELECT 1").unwrap();
+
+        match load_sql_migrations(tmp_dir.path()) {
+            Err(Error::InvalidMigrationFile(path, err)) => {
+                assert!(path.ends_with("R1__repeatable.sql"));
+                match *err {
+                    Error::InvalidName => (),
+                    _ => panic!("expected invalid name error"),
+                }
+            }
+            _ => panic!("expected invalid migration file error"),
+        }
+    }
+
+    #[test]
+    fn lists_migration_dirs_recursively() {
+        let tmp_dir = TempDir::new("keg").unwrap();
+        fs::create_dir_all(tmp_dir.path().join("nested/deeper")).unwrap();
+        fs::write(tmp_dir.path().join("nested/V1__first.sql"), "SELECT 1").unwrap();
+
+        let dirs = migration_dirs(tmp_dir.path()).unwrap();
+        assert_eq!(
+            vec![tmp_dir.path().to_path_buf(), tmp_dir.path().join("nested"), tmp_dir.path().join("nested/deeper")],
+            dirs
+        );
+
+        match migration_dirs(&tmp_dir.path().join("missing")) {
+            Err(Error::InvalidMigrationPath(path, _)) => assert!(path.ends_with("missing")),
+            _ => panic!("expected invalid migration path error"),
+        }
+    }
+}
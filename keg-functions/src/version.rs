@@ -0,0 +1,113 @@
+use crate::Error;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Migration version made of dot separated numeric components, such as 1, 1.1 or 1.2.3.
+/// Versions are compared numerically component by component, missing components count as 0,
+/// and are displayed exactly as they were written.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    components: Vec<u64>,
+    text: String,
+}
+
+impl Version {
+    // components without trailing zeros, so that 1.0 and 1 are the same version
+    fn significant(&self) -> &[u64] {
+        let len = self
+            .components
+            .iter()
+            .rposition(|c| *c != 0)
+            .map_or(0, |pos| pos + 1);
+        &self.components[..len]
+    }
+
+    pub(crate) fn components(&self) -> &[u64] {
+        &self.components
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Version, Error> {
+        let components = text
+            .split('.')
+            .map(|component| component.parse().map_err(|_| Error::InvalidVersion))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Version {
+            components,
+            text: text.into(),
+        })
+    }
+}
+
+impl From<u64> for Version {
+    fn from(version: u64) -> Version {
+        Version {
+            components: vec![version],
+            text: version.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.text)
+    }
+}
+
+impl Eq for Version {}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.significant().cmp(other.significant())
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Version;
+
+    #[test]
+    fn compares_components_numerically() {
+        let v1_2: Version = "1.2".parse().unwrap();
+        let v1_10: Version = "1.10".parse().unwrap();
+        let v2: Version = "2".parse().unwrap();
+        assert!(v1_2 < v1_10);
+        assert!(v1_10 < v2);
+    }
+
+    #[test]
+    fn ignores_trailing_zeros() {
+        let v1: Version = "1".parse().unwrap();
+        let v1_0: Version = "1.0.0".parse().unwrap();
+        assert_eq!(v1, v1_0);
+    }
+
+    #[test]
+    fn displays_version_as_written() {
+        let version: Version = "01.2".parse().unwrap();
+        assert_eq!("01.2", version.to_string());
+    }
+
+    #[test]
+    fn rejects_empty_components() {
+        assert!("1..2".parse::<Version>().is_err());
+        assert!("1.".parse::<Version>().is_err());
+    }
+}
@@ -1,41 +1,37 @@
 #![recursion_limit = "128"]
 extern crate proc_macro;
 
-use keg_functions::file_match_re;
+use keg_functions::{find_migration_files, parse_migration_files, MigrationType};
 use proc_macro::TokenStream;
 use proc_macro2::{Span as Span2, TokenStream as TokenStream2};
 use quote::quote;
 use quote::ToTokens;
-use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
-use syn::{parse_macro_input, Ident, LitStr};
-use walkdir::{DirEntry, WalkDir};
+use syn::{parse_macro_input, Expr, ExprLit, Ident, Item, Lit, LitStr};
 
-lazy_static::lazy_static! {
-    static ref RE: regex::Regex = file_match_re();
-}
-
-enum MigrationType {
-    Mod,
-    Sql,
+// paths are resolved against the dir of the crate invoking the macro,
+// so they don't depend on the dir cargo is run from
+fn manifest_dir() -> PathBuf {
+    env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::current_dir().unwrap())
 }
 
 fn find_migrations_file_names(
     location: Option<&Path>,
     mtype: MigrationType,
     full: bool,
-) -> Vec<String> {
-    //if parent dir was provided start from it, if not start from current dir
+) -> Result<Vec<String>, String> {
+    //if parent dir was provided start from it, if not start from the crate dir
     let start = match location {
-        Some(location) => fs::canonicalize(location).expect("invalid location provided"),
-        None => env::current_dir().unwrap(),
+        Some(location) => manifest_dir().join(location),
+        None => manifest_dir(),
     };
 
-    WalkDir::new(start.as_path())
+    let files = find_migration_files(&start, mtype).map_err(|err| err.to_string())?;
+    let names = files
         .into_iter()
-        .filter_map(Result::ok)
-        .map(DirEntry::into_path)
         .filter(|entry| {
             entry
                 .parent()
@@ -45,25 +41,6 @@ fn find_migrations_file_names(
                 })
                 .is_some()
         })
-        //filter entries which match the valid migration regex
-        .filter(|entry| {
-            entry
-                .as_os_str()
-                .to_str()
-                .filter(|path| RE.is_match(path))
-                .is_some()
-        })
-        //match the right extension
-        .filter(|entry| {
-            entry
-                .extension()
-                .and_then(OsStr::to_str)
-                .filter(|ext| match mtype {
-                    MigrationType::Mod => ext == &"rs",
-                    MigrationType::Sql => ext == &"sql",
-                })
-                .is_some()
-        })
         //if full is false get the name of the file without extension
         .filter_map(|entry| {
             if full {
@@ -74,17 +51,27 @@ fn find_migrations_file_names(
                     .and_then(|file| file.to_os_string().into_string().ok())
             }
         })
-        .collect()
+        .collect();
+    Ok(names)
 }
 
-fn migration_fn_quoted<T: ToTokens>(_migrations: Vec<T>) -> TokenStream2 {
+fn migration_fn_quoted<T: ToTokens>(_migrations: Vec<T>, migration_type: MigrationType) -> TokenStream2 {
+    let migration_type = match migration_type {
+        MigrationType::Mod => quote!(Mod),
+        MigrationType::Sql => quote!(Sql),
+    };
     let result = quote! {
-        use keg::{Migration, Runner};
+        use keg::{Migration, MigrationType, Runner};
         pub fn new() -> Runner {
-            let quoted_migrations: Vec<(&str, String)> = vec![#(#_migrations),*];
+            let quoted_migrations: Vec<(&str, String, bool)> = vec![#(#_migrations),*];
             let mut migrations: Vec<Migration> = Vec::new();
             for module in quoted_migrations.into_iter() {
-                migrations.push(Migration::from_filename(module.0, &module.1).unwrap());
+                let mut migration = Migration::from_filename(module.0, &module.1).unwrap();
+                migration.set_migration_type(MigrationType::#migration_type);
+                if module.2 {
+                    migration.set_no_transaction(true);
+                }
+                migrations.push(migration);
             }
             Runner::new(&migrations)
         }
@@ -92,6 +79,55 @@ fn migration_fn_quoted<T: ToTokens>(_migrations: Vec<T>) -> TokenStream2 {
     result
 }
 
+// parses the migrations while expanding so that invalid names and duplicate versions fail the build
+// instead of panicking on the first run
+fn check_migrations(migrations: &[(&str, String)]) -> Result<(), String> {
+    parse_migration_files(migrations).map(|_| ()).map_err(|err| err.to_string())
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    syn::Error::new(Span2::call_site(), msg).to_compile_error().into()
+}
+
+fn parse_mod(path: &Path) -> Result<syn::File, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("could not read migration {} content, {}", path.display(), err))?;
+    syn::parse_file(&content).map_err(|err| format!("could not parse migration {}, {}", path.display(), err))
+}
+
+// mod migrations are included as modules named after their file, which dotted versions can't be
+fn mod_ident(migration: &str, path: &Path) -> Result<Ident, String> {
+    syn::parse_str::<Ident>(migration).map_err(|_| {
+        format!(
+            "migration {} is not a valid module name, mod migrations can't have dotted versions",
+            path.display()
+        )
+    })
+}
+
+// checks if the migration module declares a down function to revert it
+fn has_down_fn(module: &syn::File) -> bool {
+    module.items.iter().any(|item| match item {
+        Item::Fn(function) => function.ident == "down",
+        _ => false,
+    })
+}
+
+// checks if the migration module declares `const NO_TRANSACTION: bool = true` for its migration, or
+// `const DOWN_NO_TRANSACTION: bool = true` for its down function, to be run outside of a transaction
+fn has_no_transaction_const(module: &syn::File, name: &str) -> bool {
+    module.items.iter().any(|item| match item {
+        Item::Const(constant) => {
+            constant.ident == name
+                && match *constant.expr {
+                    Expr::Lit(ExprLit { lit: Lit::Bool(ref value), .. }) => value.value,
+                    _ => false,
+                }
+        }
+        _ => false,
+    })
+}
+
 #[proc_macro]
 pub fn include_migration_mods(input: TokenStream) -> TokenStream {
     let _location = if input.is_empty() {
@@ -102,20 +138,50 @@ pub fn include_migration_mods(input: TokenStream) -> TokenStream {
     };
 
     let location = _location.as_ref().map(Path::new);
-    let migration_mod_names = find_migrations_file_names(location, MigrationType::Mod, false);
+    let migration_mod_paths = match find_migrations_file_names(location, MigrationType::Mod, true) {
+        Ok(paths) => paths,
+        Err(msg) => return compile_error(&msg),
+    };
+    // the sql of mod migrations is only known at runtime, only their names are checked,
+    // before they are used as module names
+    let checked = migration_mod_paths
+        .iter()
+        .map(|path| (path.as_str(), String::new()))
+        .collect::<Vec<_>>();
+    if let Err(msg) = check_migrations(&checked) {
+        return compile_error(&msg);
+    }
+
     let mut migrations_mods = Vec::new();
     let mut _migrations = Vec::new();
-
-    for migration in migration_mod_names.iter() {
+    for migration_path in migration_mod_paths.iter() {
+        let path = Path::new(migration_path);
+        let migration = path
+            .file_stem()
+            .and_then(|file| file.to_os_string().into_string().ok())
+            .unwrap();
         log::debug!("including mod {}", migration);
 
-        let ident = Ident::new(migration, Span2::call_site());
+        let ident = match mod_ident(&migration, path) {
+            Ok(ident) => ident,
+            Err(msg) => return compile_error(&msg),
+        };
         let mig_mod = quote! {pub mod #ident;};
-        _migrations.push(quote! {(#migration, #ident::migration())});
+        let module = match parse_mod(path) {
+            Ok(module) => module,
+            Err(msg) => return compile_error(&msg),
+        };
+        let no_transaction = has_no_transaction_const(&module, "NO_TRANSACTION");
+        _migrations.push(quote! {(#migration, #ident::migration(), #no_transaction)});
+        if migration.starts_with('V') && has_down_fn(&module) {
+            let undo = format!("U{}", &migration[1..]);
+            let down_no_transaction = has_no_transaction_const(&module, "DOWN_NO_TRANSACTION");
+            _migrations.push(quote! {(#undo, #ident::down(), #down_no_transaction)});
+        }
         migrations_mods.push(mig_mod);
     }
 
-    let fnq = migration_fn_quoted(_migrations);
+    let fnq = migration_fn_quoted(_migrations, MigrationType::Mod);
     let result = quote! {
         #(#migrations_mods)*
 
@@ -134,19 +200,33 @@ pub fn embed_migrations(input: TokenStream) -> TokenStream {
     };
 
     let location = _location.as_ref().map(Path::new);
-    let migration_paths = find_migrations_file_names(location, MigrationType::Sql, true);
+    let migration_paths = match find_migrations_file_names(location, MigrationType::Sql, true) {
+        Ok(paths) => paths,
+        Err(msg) => return compile_error(&msg),
+    };
     let mut _migrations = Vec::new();
+    let mut checked = Vec::new();
     for migration_path in migration_paths.iter() {
-        let sql = fs::read_to_string(migration_path)
-            .unwrap_or_else(|_| panic!("could not read migration {} content", migration_path));
+        let sql = match fs::read_to_string(migration_path) {
+            Ok(sql) => sql,
+            Err(err) => return compile_error(&format!("could not read migration {} content, {}", migration_path, err)),
+        };
         let migration_name = Path::new(migration_path)
             .file_stem()
             .and_then(|file| file.to_os_string().into_string().ok())
             .unwrap();
-        _migrations.push(quote! {(#migration_name, #sql.into())});
+        // include_str makes cargo track the file, so editing it rebuilds the crate. cargo can't track
+        // directories from a proc macro, added or removed files are picked up by calling
+        // keg::rerun_if_migrations_changed from the build script
+        _migrations.push(quote! {(#migration_name, include_str!(#migration_path).into(), false)});
+        checked.push((migration_path.as_str(), sql));
     }
 
-    let fnq = migration_fn_quoted(_migrations);
+    if let Err(msg) = check_migrations(&checked) {
+        return compile_error(&msg);
+    }
+
+    let fnq = migration_fn_quoted(_migrations, MigrationType::Sql);
     (quote! {
         pub mod migrations {
             #fnq
@@ -157,7 +237,10 @@ pub fn embed_migrations(input: TokenStream) -> TokenStream {
 
 #[cfg(test)]
 mod tests {
-    use super::{find_migrations_file_names, migration_fn_quoted, MigrationType};
+    use super::{
+        check_migrations, find_migrations_file_names, has_down_fn, has_no_transaction_const, migration_fn_quoted,
+        mod_ident, parse_mod, MigrationType,
+    };
     use quote::quote;
     use std::fs;
     use std::path::Path;
@@ -172,8 +255,7 @@ mod tests {
         let mod2 = tmp_dir.path().join("migrations/V2__second.rs");
         fs::File::create(&mod2).unwrap();
 
-        let mut mods = find_migrations_file_names(None, MigrationType::Mod, false);
-        mods.sort();
+        let mods = find_migrations_file_names(None, MigrationType::Mod, false).unwrap();
         assert_eq!("V1__first", mods[0]);
         assert_eq!("V2__second", mods[1]);
     }
@@ -186,8 +268,7 @@ mod tests {
         let mod2 = tmp_dir.path().join("V2__second.rs");
         fs::File::create(&mod2).unwrap();
 
-        let mut mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Mod, false);
-        mods.sort();
+        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Mod, false).unwrap();
         assert_eq!("V1__first", mods[0]);
         assert_eq!("V2__second", mods[1]);
     }
@@ -201,10 +282,44 @@ mod tests {
         let mod2 = tmp_dir.path().join("migrations/V2second.rs");
         fs::File::create(&mod2).unwrap();
 
-        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Mod, false);
+        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Mod, false).unwrap();
         assert!(mods.is_empty());
     }
 
+    #[test]
+    fn detects_mod_migration_down_fn() {
+        let tmp_dir = TempDir::new_in(".", "keg").unwrap();
+        let mod1 = tmp_dir.path().join("V1__first.rs");
+        fs::write(&mod1, "pub fn migration() -> String { String::new() }").unwrap();
+        let mod2 = tmp_dir.path().join("V2__second.rs");
+        fs::write(
+            &mod2,
+            "pub fn migration() -> String { String::new() }\npub fn down() -> String { String::new() }",
+        )
+        .unwrap();
+
+        assert!(!has_down_fn(&parse_mod(&mod1).unwrap()));
+        assert!(has_down_fn(&parse_mod(&mod2).unwrap()));
+    }
+
+    #[test]
+    fn detects_mod_migration_no_transaction_const() {
+        let tmp_dir = TempDir::new_in(".", "keg").unwrap();
+        let mod1 = tmp_dir.path().join("V1__first.rs");
+        fs::write(&mod1, "pub const NO_TRANSACTION: bool = false;
+pub fn migration() -> String { String::new() }").unwrap();
+        let mod2 = tmp_dir.path().join("V2__second.rs");
+        fs::write(&mod2, "pub const NO_TRANSACTION: bool = true;
+pub fn migration() -> String { String::new() }
+pub const DOWN_NO_TRANSACTION: bool = true;
+pub fn down() -> String { String::new() }").unwrap();
+
+        assert!(!has_no_transaction_const(&parse_mod(&mod1).unwrap(), "NO_TRANSACTION"));
+        assert!(has_no_transaction_const(&parse_mod(&mod2).unwrap(), "NO_TRANSACTION"));
+        assert!(!has_no_transaction_const(&parse_mod(&mod1).unwrap(), "DOWN_NO_TRANSACTION"));
+        assert!(has_no_transaction_const(&parse_mod(&mod2).unwrap(), "DOWN_NO_TRANSACTION"));
+    }
+
     #[test]
     fn finds_sql_migrations() {
         let tmp_dir = TempDir::new_in("./", "keg").unwrap();
@@ -214,8 +329,7 @@ mod tests {
         let sql2 = tmp_dir.path().join("migrations/V2__second.sql");
         fs::File::create(&sql2).unwrap();
 
-        let mut mods = find_migrations_file_names(None, MigrationType::Sql, true);
-        mods.sort();
+        let mods = find_migrations_file_names(None, MigrationType::Sql, true).unwrap();
         assert_eq!(sql1, Path::new(&mods[0]));
         assert_eq!(sql2, Path::new(&mods[1]));
     }
@@ -228,8 +342,7 @@ mod tests {
         let sql2 = tmp_dir.path().join("V2__second.sql");
         fs::File::create(&sql2).unwrap();
 
-        let mut mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Sql, true);
-        mods.sort();
+        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Sql, true).unwrap();
         assert_eq!(sql1, Path::new(&mods[0]));
         assert_eq!(sql2, Path::new(&mods[1]));
     }
@@ -243,23 +356,67 @@ mod tests {
         let sql2 = tmp_dir.path().join("migrations/V2second.sql");
         fs::File::create(&sql2).unwrap();
 
-        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Sql, true);
+        let mods = find_migrations_file_names(Some(tmp_dir.path()), MigrationType::Sql, true).unwrap();
         assert!(mods.is_empty());
     }
 
     #[test]
     fn test_quote_fn() {
-        let migs = vec![quote!("V1__first", "valid_sql_file")];
+        let migs = vec![quote!("V1__first", "valid_sql_file", false)];
         let expected = concat! {
-            "use keg :: { Migration , Runner } ; ",
+            "use keg :: { Migration , MigrationType , Runner } ; ",
             "pub fn new ( ) -> Runner { ",
-            "let quoted_migrations : Vec < ( & str , String ) > = vec ! [ \"V1__first\" , \"valid_sql_file\" ] ; ",
+            "let quoted_migrations : Vec < ( & str , String , bool ) > = vec ! [ \"V1__first\" , \"valid_sql_file\" , false ] ; ",
             "let mut migrations : Vec < Migration > = Vec :: new ( ) ; ",
             "for module in quoted_migrations . into_iter ( ) { ",
-            "migrations . push ( Migration :: from_filename ( module . 0 , & module . 1 ) . unwrap ( ) ) ; ",
+            "let mut migration = Migration :: from_filename ( module . 0 , & module . 1 ) . unwrap ( ) ; ",
+            "migration . set_migration_type ( MigrationType :: Sql ) ; ",
+            "if module . 2 { ",
+            "migration . set_no_transaction ( true ) ; ",
+            "} ",
+            "migrations . push ( migration ) ; ",
             "} ",
             "Runner :: new ( & migrations ) }"
         };
-        assert_eq!(expected, migration_fn_quoted(migs).to_string());
+        assert_eq!(expected, migration_fn_quoted(migs, MigrationType::Sql).to_string());
+    }
+
+    #[test]
+    fn checks_migration_names_and_versions() {
+        let valid = vec![
+            ("V1__first.sql", String::new()),
+            ("U1__first.sql", String::new()),
+            ("R__view.sql", String::new()),
+        ];
+        assert!(check_migrations(&valid).is_ok());
+
+        let invalid = vec![("R1__view.sql", String::new())];
+        let err = check_migrations(&invalid).unwrap_err();
+        assert!(err.contains("R1__view.sql"));
+
+        let duplicate = vec![("V2__a.sql", String::new()), ("V2__b.sql", String::new())];
+        let err = check_migrations(&duplicate).unwrap_err();
+        assert!(err.contains("V2__a.sql") && err.contains("V2__b.sql"));
+    }
+
+    #[test]
+    fn reports_invalid_mod_migrations() {
+        let tmp_dir = TempDir::new_in(".", "keg").unwrap();
+        let mod1 = tmp_dir.path().join("V1.1__first.rs");
+        fs::write(&mod1, "pub fn migration() -> String { String::new() }").unwrap();
+        let mod2 = tmp_dir.path().join("V2__second.rs");
+        fs::write(&mod2, "pub fn migration() -> String {").unwrap();
+
+        assert!(mod_ident("V1.1__first", &mod1).unwrap_err().contains("V1.1__first.rs"));
+        assert!(mod_ident("V2__second", &mod2).is_ok());
+        match parse_mod(&mod2) {
+            Err(msg) => assert!(msg.contains("V2__second.rs")),
+            Ok(_) => panic!("expected parse error"),
+        }
+
+        let missing = tmp_dir.path().join("missing");
+        assert!(find_migrations_file_names(Some(&missing), MigrationType::Mod, true)
+            .unwrap_err()
+            .contains("missing"));
     }
 }
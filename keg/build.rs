@@ -0,0 +1,7 @@
+use std::path::Path;
+
+// the tests embed these with embed_migrations!, which can't track added or removed migrations
+fn main() {
+    keg_functions::rerun_if_migrations_changed(Path::new("tests/sql_migrations")).unwrap();
+    keg_functions::rerun_if_migrations_changed(Path::new("tests/sql_migrations_broken")).unwrap();
+}
@@ -1,2 +1,8 @@
-pub use keg_functions::{Migration, Runner, Migrate, MigrateGrouped};
+pub use keg_functions::{
+    AppliedMigration, AppliedMigrationType, Error, Migration, MigrationInfo, MigrationReport, MigrationState, Plan, Report, Runner,
+    Lock, Migrate, MigrateGrouped, MigrationType, Version, load_sql_migrations, rerun_if_migrations_changed,
+};
 pub use keg_macros::{embed_migrations, include_migration_mods};
+
+#[cfg(feature = "async")]
+pub use keg_functions::{AsyncLock, AsyncMigrate, AsyncQuery, AsyncTransaction};
@@ -0,0 +1,5 @@
+use std::path::Path;
+
+fn main() {
+    keg::rerun_if_migrations_changed(Path::new("src/migrations")).unwrap();
+}
@@ -1,16 +1,19 @@
 mod postgres {
-    use chrono::{DateTime, Local};
-    use keg::{Migrate as _, Migration};
+    use chrono::{DateTime, Local, Utc};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::time::Duration;
+    use keg::{Error, Lock as _, Migrate as _, Migration, MigrationState, Runner};
     use ttpostgres::{Connection, TlsMode};
 
     mod embedded {
         use keg::embed_migrations;
-        embed_migrations!("keg/tests/sql_migrations");
+        embed_migrations!("tests/sql_migrations");
     }
 
     mod broken {
         use keg::embed_migrations;
-        embed_migrations!("keg/tests/sql_migrations_broken");
+        embed_migrations!("tests/sql_migrations_broken");
     }
 
     fn clean_database() {
@@ -125,17 +128,16 @@ mod postgres {
             .query("SELECT MAX(version) FROM keg_schema_history", &[])
             .unwrap()
         {
-            let current = row.get(0);
-            assert_eq!(3, current);
+            let current: String = row.get(0);
+            assert_eq!("3", current);
         }
 
         for row in &conn
             .query("SELECT installed_on FROM keg_schema_history where version=(SELECT MAX(version) from keg_schema_history)", &[])
             .unwrap()
         {
-            let _installed_on: String = row.get(0);
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local);
-            assert_eq!(Local::today(), installed_on.date());
+            let installed_on: DateTime<Utc> = row.get(0);
+            assert_eq!(Local::today(), installed_on.with_timezone(&Local).date());
         }
         clean_database();
     }
@@ -154,17 +156,16 @@ mod postgres {
             .query("SELECT MAX(version) FROM keg_schema_history", &[])
             .unwrap()
         {
-            let current = row.get(0);
-            assert_eq!(3, current);
+            let current: String = row.get(0);
+            assert_eq!("3", current);
         }
 
         for row in &conn
             .query("SELECT installed_on FROM keg_schema_history where version=(SELECT MAX(version) from keg_schema_history)", &[])
             .unwrap()
         {
-            let _installed_on: String = row.get(0);
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local);
-            assert_eq!(Local::today(), installed_on.date());
+            let installed_on: DateTime<Utc> = row.get(0);
+            assert_eq!(Local::today(), installed_on.with_timezone(&Local).date());
         }
         clean_database();
     }
@@ -180,11 +181,11 @@ mod postgres {
         assert!(result.is_err());
 
         for row in &conn
-            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .query("SELECT MAX(version) FROM keg_schema_history WHERE success", &[])
             .unwrap()
         {
-            let current = row.get(0);
-            assert_eq!(2, current);
+            let current: String = row.get(0);
+            assert_eq!("2", current);
         }
 
         clean_database();
@@ -241,17 +242,16 @@ mod postgres {
             .query("SELECT MAX(version) FROM keg_schema_history", &[])
             .unwrap()
         {
-            let current = row.get(0);
-            assert_eq!(3, current);
+            let current: String = row.get(0);
+            assert_eq!("3", current);
         }
 
         for row in &conn
             .query("SELECT installed_on FROM keg_schema_history where version=(SELECT MAX(version) from keg_schema_history)", &[])
             .unwrap()
         {
-            let _installed_on: String = row.get(0);
-            let installed_on = DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local);
-            assert_eq!(Local::today(), installed_on.date());
+            let installed_on: DateTime<Utc> = row.get(0);
+            assert_eq!(Local::today(), installed_on.with_timezone(&Local).date());
         }
         clean_database();
     }
@@ -269,16 +269,859 @@ mod postgres {
         )
         .unwrap();
         let mchecksum = migration.checksum();
-        conn.migrate(&[migration]).unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
 
         for row in &conn
             .query("SELECT version, checksum FROM keg_schema_history where version = (SELECT MAX(version) from keg_schema_history)", &[])
             .unwrap()
         {
-            let current = row.get(0);
+            let current: String = row.get(0);
+            let checksum: String = row.get(1);
+            assert_eq!("4", current);
+            assert_eq!(mchecksum.to_string(), checksum);
+        }
+    }
+
+    #[test]
+    fn aborts_on_divergent_migration() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename(
+            "V2__add_cars_table",
+            "CREATE TABLE cars (id int, name varchar(255), year int);",
+        )
+        .unwrap();
+        let result = conn.migrate(&[migration], false, None);
+
+        match result {
+            Err(Error::DivergentVersions(divergent)) => {
+                assert_eq!(1, divergent.len());
+                assert_eq!("V2__add_cars_table", divergent[0].1.to_string());
+            }
+            _ => panic!("expected divergent versions error"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn upgrades_legacy_checksums() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        // checksum as stored by previous keg versions
+        let sql = include_str!("sql_migrations/V1__initial.sql");
+        let mut hasher = DefaultHasher::new();
+        "initial".hash(&mut hasher);
+        1usize.hash(&mut hasher);
+        sql.hash(&mut hasher);
+        conn.execute(
+            "UPDATE keg_schema_history SET checksum = $1 WHERE version = 1",
+            &[&hasher.finish().to_string()],
+        )
+        .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename("V1__initial", sql).unwrap();
+        for row in &conn
+            .query("SELECT checksum FROM keg_schema_history where version = 1", &[])
+            .unwrap()
+        {
+            let checksum: String = row.get(0);
+            assert_eq!(migration.checksum().to_string(), checksum);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn applies_repeatable_migration_when_changed() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = [Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap()];
+        conn.migrate(&migrations, false, None).unwrap();
+        // unchanged repeatable migrations are not applied again
+        conn.migrate(&migrations, false, None).unwrap();
+
+        let migration = Migration::from_filename("R__cars_view", "CREATE VIEW cars_v2 AS SELECT 2").unwrap();
+        let mchecksum = migration.checksum();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        for row in &conn
+            .query("SELECT COUNT(*), MAX(checksum) FROM keg_schema_history WHERE version IS NULL AND name = 'cars_view'", &[])
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
             let checksum: String = row.get(1);
-            assert_eq!(4, current);
+            assert_eq!(1, count);
             assert_eq!(mchecksum.to_string(), checksum);
         }
+        clean_database();
+    }
+
+    #[test]
+    fn upgrades_schema_history_table_with_version_primary_key() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            &[],
+        )
+        .unwrap();
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        for row in &conn
+            .query("SELECT COUNT(*) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(4, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn rolls_back_to_version() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+            Migration::from_filename("U2__add_cars_table", "DROP TABLE cars").unwrap(),
+        ];
+        let runner = Runner::new(&migrations);
+        runner.run(&mut conn).unwrap();
+        runner.rollback_to(&mut conn, 1.into()).unwrap();
+
+        for row in &conn
+            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("1", current);
+        }
+
+        let tables = conn
+            .query("SELECT table_name FROM information_schema.tables WHERE table_name='cars'", &[])
+            .unwrap();
+        assert!(tables.is_empty());
+        clean_database();
+    }
+
+    #[test]
+    fn rollback_aborts_on_missing_undo_migration() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+        let result = embedded::migrations::new().rollback_to(&mut conn, 1.into());
+
+        match result {
+            Err(Error::MissingUndoMigration(_)) => (),
+            _ => panic!("expected missing undo migration error"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn applies_out_of_order_migration_only_when_enabled() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V3__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        Runner::new(&migrations).run(&mut conn).unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_brand_to_cars_table", include_str!("sql_migrations/V3__add_brand_to_cars_table.sql")).unwrap(),
+            Migration::from_filename("V3__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        let mut runner = Runner::new(&migrations);
+        match runner.run(&mut conn) {
+            Err(Error::OutOfOrderMigrations(migrations)) => {
+                assert_eq!("V2__add_brand_to_cars_table", migrations[0].to_string());
+            }
+            _ => panic!("expected out of order migrations error"),
+        }
+
+        runner.set_out_of_order(true);
+        runner.run(&mut conn).unwrap();
+
+        for row in &conn
+            .query("SELECT COUNT(*) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(3, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn applies_dotted_version_migrations() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1.2__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V1.10__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        Runner::new(&migrations).run(&mut conn).unwrap();
+
+        let versions: Vec<String> = conn
+            .query("SELECT version FROM keg_schema_history ORDER BY installed_on, name", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["1.2", "1.10"], versions);
+        clean_database();
+    }
+
+    #[test]
+    fn applies_timestamp_version_migration_on_legacy_schema_history_table() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            &[],
+        )
+        .unwrap();
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename(
+            "V20261017093000__add_year_to_cars_table",
+            "ALTER TABLE cars ADD year INTEGER;",
+        )
+        .unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        for row in &conn
+            .query("SELECT version FROM keg_schema_history WHERE name = 'add_year_to_cars_table'", &[])
+            .unwrap()
+        {
+            let version: String = row.get(0);
+            assert_eq!("20261017093000", version);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn applies_migrations_above_baseline() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        conn.batch_execute(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.batch_execute(include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap();
+
+        let runner = embedded::migrations::new();
+        runner.baseline(&mut conn, 2.into(), "existing schema").unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let names: Vec<String> = conn
+            .query("SELECT name FROM keg_schema_history ORDER BY version", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["existing schema", "add_brand_to_cars_table"], names);
+        clean_database();
+    }
+
+    #[test]
+    fn baseline_aborts_on_existing_schema_history() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        match embedded::migrations::new().baseline(&mut conn, 2.into(), "existing schema") {
+            Err(Error::SchemaHistoryNotEmpty) => (),
+            _ => panic!("expected schema history not empty error"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn migrates_to_target_version() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_target(2.into());
+        runner.run(&mut conn).unwrap();
+
+        for row in &conn
+            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("2", current);
+        }
+
+        runner.set_target(1.into());
+        match runner.run(&mut conn) {
+            Err(Error::TargetVersionBelowCurrent(target, current)) => {
+                assert_eq!("1", target.to_string());
+                assert_eq!("2", current.to_string());
+            }
+            _ => panic!("expected target version below current error"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn plans_pending_migrations_without_applying_them() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_target(2.into());
+        runner.run(&mut conn).unwrap();
+
+        let plan = embedded::migrations::new().plan(&mut conn).unwrap();
+        let pending = plan.pending().iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(vec!["V3__add_brand_to_cars_table"], pending);
+        assert!(plan.problems().is_empty());
+
+        let migration = Migration::from_filename(
+            "V2__add_cars_table",
+            "CREATE TABLE cars (id int);",
+        )
+        .unwrap();
+        let plan = Runner::new(&[migration]).plan(&mut conn).unwrap();
+        assert!(plan.pending().is_empty());
+        match plan.problems() {
+            [Error::DivergentVersions(divergent)] => assert_eq!(1, divergent.len()),
+            _ => panic!("expected divergent versions problem"),
+        }
+
+        for row in &conn
+            .query("SELECT COUNT(*) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(2, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn plans_and_lists_without_creating_schema_history() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let runner = embedded::migrations::new();
+        let plan = runner.plan(&mut conn).unwrap();
+        assert_eq!(3, plan.pending().len());
+        assert!(plan.problems().is_empty());
+        let info = runner.info(&mut conn).unwrap();
+        assert!(info.iter().all(|i| i.state() == MigrationState::Pending));
+
+        for row in &conn
+            .query(
+                "SELECT COUNT(*) FROM information_schema.tables WHERE table_name='keg_schema_history'", &[]
+            )
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(0, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn reports_applied_migrations() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report
+            .applied()
+            .iter()
+            .map(|m| (m.version().unwrap().to_string(), m.name().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("1".to_string(), "initial".to_string()),
+                ("2".to_string(), "add_cars_table".to_string()),
+                ("3".to_string(), "add_brand_to_cars_table".to_string()),
+            ],
+            applied
+        );
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        assert!(report.applied().is_empty());
+        clean_database();
+    }
+
+    #[test]
+    fn reports_migrations_applied_before_failure() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        match broken::migrations::new().run(&mut conn) {
+            Err(Error::RunAborted(_, report)) => assert_eq!(2, report.applied().len()),
+            _ => panic!("expected run aborted error"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn lists_migrations_info() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", "CREATE TABLE cars (id int);").unwrap(),
+            Migration::from_filename("V4__add_year_to_cars_table", "ALTER TABLE cars ADD year INTEGER;").unwrap(),
+            Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap(),
+        ];
+        let info = Runner::new(&migrations).info(&mut conn).unwrap();
+        let states = info.iter().map(|i| i.state()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                MigrationState::Applied,
+                MigrationState::ChecksumMismatch,
+                MigrationState::MissingLocally,
+                MigrationState::Pending,
+                MigrationState::Pending,
+            ],
+            states
+        );
+        assert_eq!("add_brand_to_cars_table", info[2].applied().unwrap().name());
+        clean_database();
+    }
+
+    #[test]
+    fn reruns_failed_migration_rolled_back() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        broken::migrations::new().run(&mut conn).unwrap_err();
+        for row in &conn
+            .query("SELECT COUNT(*) FROM keg_schema_history WHERE NOT success", &[])
+            .unwrap()
+        {
+            let failed: i64 = row.get(0);
+            assert_eq!(0, failed);
+        }
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        for row in &conn
+            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("3", current);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn aborts_on_failed_migration_until_repaired() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let broken = vec![Migration::from_filename(
+            "V1__add_notes",
+            "-- keg:no-transaction\nCREATE TABLE notes(id int);\nINSERT INTO missing VALUES (1);",
+        )
+        .unwrap()];
+        Runner::new(&broken).run(&mut conn).unwrap_err();
+
+        // fixing the sql changes the checksum, the failed attempt is still the one reported
+        let fixed = vec![Migration::from_filename(
+            "V1__add_notes",
+            "-- keg:no-transaction\nCREATE TABLE IF NOT EXISTS notes(id int);\nINSERT INTO notes VALUES (1);",
+        )
+        .unwrap()];
+        let runner = Runner::new(&fixed);
+        match runner.run(&mut conn) {
+            Err(Error::FailedMigrations(failed)) => {
+                assert_eq!("V1__add_notes", failed[0].to_string());
+            }
+            _ => panic!("expected failed migrations error"),
+        }
+
+        runner.repair(&mut conn).unwrap();
+        runner.run(&mut conn).unwrap();
+
+        for row in &conn.query("SELECT COUNT(*) FROM notes", &[]).unwrap() {
+            let count: i64 = row.get(0);
+            assert_eq!(1, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn waits_for_migrations_lock() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+        let mut other =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+        assert!(other.lock(Duration::from_secs(1)).unwrap());
+
+        let mut runner = embedded::migrations::new();
+        runner.set_lock_timeout(Duration::from_millis(200));
+        match runner.run(&mut conn) {
+            Err(Error::LockTimeout(_)) => (),
+            _ => panic!("expected lock timeout error"),
+        }
+
+        other.unlock().unwrap();
+        runner.run(&mut conn).unwrap();
+        clean_database();
+    }
+
+    #[test]
+    fn applies_multi_statement_migration() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_cars_and_motos",
+            "CREATE TABLE cars(id int);\nCREATE TABLE motos(id int);\nINSERT INTO motos VALUES (1);",
+        )
+        .unwrap();
+        let report = conn.migrate(&[migration], false, None).unwrap();
+        assert_eq!(1, report.applied()[0].rows_affected());
+
+        for row in &conn.query("SELECT COUNT(*) FROM motos", &[]).unwrap() {
+            let motos: i64 = row.get(0);
+            assert_eq!(1, motos);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn reports_failed_statement() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_cars",
+            "CREATE TABLE cars(id int);\nINSERT INTO motos VALUES (1);",
+        )
+        .unwrap();
+        let err = conn.migrate(&[migration], false, None).unwrap_err();
+        assert!(err.to_string().contains("statement 2: INSERT INTO motos VALUES (1)"));
+        clean_database();
+    }
+
+    #[test]
+    fn applies_function_migration() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_one_function",
+            "CREATE FUNCTION one() RETURNS int AS $$\n\
+             BEGIN\n\
+               RETURN 1;\n\
+             END;\n\
+             $$ LANGUAGE plpgsql;\n\
+             CREATE TABLE cars(id int DEFAULT one());",
+        )
+        .unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        for row in &conn.query("SELECT one()", &[]).unwrap() {
+            let one: i32 = row.get(0);
+            assert_eq!(1, one);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn applies_no_transaction_migration() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = vec![
+            Migration::from_filename("V1__add_cars", "CREATE TABLE cars(id int);").unwrap(),
+            Migration::from_filename(
+                "V2__index_cars",
+                "-- keg:no-transaction\nCREATE INDEX CONCURRENTLY cars_id ON cars(id);",
+            )
+            .unwrap(),
+        ];
+        let report = Runner::new(&migrations).run(&mut conn).unwrap();
+        assert_eq!(2, report.applied().len());
+        clean_database();
+    }
+
+    #[test]
+    fn refuses_no_transaction_migration_when_grouped() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migrations = vec![
+            Migration::from_filename("V1__add_cars", "CREATE TABLE cars(id int);").unwrap(),
+            Migration::from_filename(
+                "V2__index_cars",
+                "-- keg:no-transaction\nCREATE INDEX CONCURRENTLY cars_id ON cars(id);",
+            )
+            .unwrap(),
+        ];
+        let mut runner = Runner::new(&migrations);
+        runner.set_grouped(true);
+        match runner.plan(&mut conn).unwrap().problems() {
+            [Error::NoTransactionMigrationsGrouped(migrations)] => assert_eq!(1, migrations.len()),
+            _ => panic!("expected no-transaction migrations problem"),
+        }
+        match runner.run(&mut conn) {
+            Err(Error::NoTransactionMigrationsGrouped(migrations)) => {
+                assert_eq!(1, migrations.len());
+                assert_eq!("index_cars", migrations[0].name());
+            }
+            _ => panic!("test failed"),
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn records_baseline_description_verbatim() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let description = "schema of the 'legacy' app; is it? -- yes";
+        Runner::new(&[]).baseline(&mut conn, 2.into(), description).unwrap();
+
+        for row in &conn.query("SELECT name FROM keg_schema_history", &[]).unwrap() {
+            let name: String = row.get(0);
+            assert_eq!(description, name);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn records_migration_details_in_schema_history() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        for row in &conn
+            .query("SELECT description, type, installed_by, execution_time_ms FROM keg_schema_history WHERE version = '2'", &[])
+            .unwrap()
+        {
+            let description: Option<String> = row.get(0);
+            let migration_type: String = row.get(1);
+            let installed_by: String = row.get(2);
+            let execution_time_ms: Option<i64> = row.get(3);
+            // the migration has no keg:description directive
+            assert_eq!(None, description);
+            assert_eq!("SQL", migration_type);
+            assert_eq!("postgres", installed_by);
+            assert!(execution_time_ms.unwrap() >= 0);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn records_baseline_and_mod_migration_types() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let runner = mod_migrations::migrations::new();
+        runner.baseline(&mut conn, 1.into(), "existing schema").unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let types: Vec<(String, String)> = conn
+            .query("SELECT version, type FROM keg_schema_history ORDER BY version", &[])
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("1".to_string(), "BASELINE".to_string()),
+                ("2".to_string(), "RUST".to_string()),
+                ("3".to_string(), "RUST".to_string()),
+            ],
+            types
+        );
+        clean_database();
+    }
+
+    #[test]
+    fn upgrades_legacy_schema_history_rows() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.batch_execute(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            &[],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES (1, 'initial', '2026-10-17T11:30:00.250+02:00', $1)",
+            &[&migration.checksum().to_string()],
+        )
+        .unwrap();
+        let report = conn.migrate(&[migration], false, None).unwrap();
+        assert!(report.applied().is_empty());
+
+        for row in &conn
+            .query("SELECT version, installed_on, type FROM keg_schema_history", &[])
+            .unwrap()
+        {
+            let version: String = row.get(0);
+            let installed_on: DateTime<Utc> = row.get(1);
+            let migration_type: Option<String> = row.get(2);
+            assert_eq!("1", version);
+            assert_eq!(DateTime::parse_from_rfc3339("2026-10-17T11:30:00.250+02:00").unwrap(), installed_on);
+            assert_eq!(None, migration_type);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn finishes_upgrade_interrupted_after_dropping_schema_history() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.batch_execute(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history_upgrade(version VARCHAR(50) UNIQUE, name VARCHAR(255), \
+             description VARCHAR(255), type VARCHAR(20), installed_on TIMESTAMP WITH TIME ZONE, installed_by VARCHAR(100), \
+             execution_time_ms BIGINT, checksum VARCHAR(255), success BOOLEAN)",
+            &[],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history_upgrade (version, name, installed_on, checksum) VALUES ('1', 'initial', NOW(), $1)",
+            &[&migration.checksum().to_string()],
+        )
+        .unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report.applied().iter().map(|m| m.version().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["2", "3"], applied);
+
+        for row in &conn
+            .query("SELECT COUNT(*) FROM information_schema.tables WHERE table_name='keg_schema_history_upgrade'", &[])
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(0, count);
+        }
+        clean_database();
+    }
+
+    #[test]
+    fn restarts_upgrade_interrupted_before_dropping_schema_history() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.batch_execute(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            &[],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES (1, 'initial', '2026-10-17T11:30:00+02:00', $1)",
+            &[&migration.checksum().to_string()],
+        )
+        .unwrap();
+        // the upgrade table already holds the copied row
+        conn.execute(
+            "CREATE TABLE keg_schema_history_upgrade(version VARCHAR(50) UNIQUE, name VARCHAR(255), \
+             description VARCHAR(255), type VARCHAR(20), installed_on TIMESTAMP WITH TIME ZONE, installed_by VARCHAR(100), \
+             execution_time_ms BIGINT, checksum VARCHAR(255), success BOOLEAN)",
+            &[],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO keg_schema_history_upgrade (version, name) VALUES ('1', 'initial')", &[])
+            .unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report.applied().iter().map(|m| m.version().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["2", "3"], applied);
+        clean_database();
+    }
+
+    #[test]
+    fn plans_without_upgrading_legacy_schema_history_table() {
+        let mut conn =
+            Connection::connect("postgres://postgres@localhost:5432/postgres", TlsMode::None)
+                .unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            &[],
+        )
+        .unwrap();
+
+        let plan = embedded::migrations::new().plan(&mut conn).unwrap();
+        assert!(plan.pending().is_empty());
+        match plan.problems() {
+            [Error::SchemaHistoryNeedsUpgrade] => (),
+            _ => panic!("expected schema history needs upgrade problem"),
+        }
+        match embedded::migrations::new().info(&mut conn) {
+            Err(Error::SchemaHistoryNeedsUpgrade) => (),
+            _ => panic!("expected schema history needs upgrade error"),
+        }
+
+        // the new columns would have been added by an upgrade
+        assert!(conn.query("SELECT type FROM keg_schema_history", &[]).is_err());
+        clean_database();
     }
 }
@@ -1,16 +1,19 @@
 mod rusqlite {
-    use chrono::{DateTime, Local};
-    use keg::{Migrate as _, Migration};
+    use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::time::Duration;
+    use keg::{AppliedMigrationType, Error, Lock as _, Migrate as _, Migration, MigrationState, Runner};
     use ttrusqlite::{Connection, NO_PARAMS};
 
     mod embedded {
         use keg::embed_migrations;
-        embed_migrations!("keg/tests/sql_migrations");
+        embed_migrations!("tests/sql_migrations");
     }
 
     mod broken {
         use keg::embed_migrations;
-        embed_migrations!("keg/tests/sql_migrations_broken");
+        embed_migrations!("tests/sql_migrations_broken");
     }
 
     #[test]
@@ -91,14 +94,14 @@ mod rusqlite {
 
         embedded::migrations::new().run(&mut conn).unwrap();
 
-        let current: u32 = conn
+        let current: String = conn
             .query_row(
                 "SELECT MAX(version) FROM keg_schema_history",
                 NO_PARAMS,
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(3, current);
+        assert_eq!("3", current);
 
         let installed_on: DateTime<Local> = conn
             .query_row(
@@ -106,7 +109,8 @@ mod rusqlite {
                 NO_PARAMS,
                 |row| {
                     let _installed_on: String = row.get(0).unwrap();
-                    Ok(DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local))
+                    let _installed_on = NaiveDateTime::parse_from_str(&_installed_on, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+                    Ok(Utc.from_utc_datetime(&_installed_on).with_timezone(&Local))
                 }
             )
             .unwrap();
@@ -121,14 +125,14 @@ mod rusqlite {
         runner.set_grouped(false);
         runner.run(&mut conn).unwrap();
 
-        let current: u32 = conn
+        let current: String = conn
             .query_row(
                 "SELECT MAX(version) FROM keg_schema_history",
                 NO_PARAMS,
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(3, current);
+        assert_eq!("3", current);
 
         let installed_on: DateTime<Local> = conn
             .query_row(
@@ -136,7 +140,8 @@ mod rusqlite {
                 NO_PARAMS,
                 |row| {
                     let _installed_on: String = row.get(0).unwrap();
-                    Ok(DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local))
+                    let _installed_on = NaiveDateTime::parse_from_str(&_installed_on, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+                    Ok(Utc.from_utc_datetime(&_installed_on).with_timezone(&Local))
                 }
             )
             .unwrap();
@@ -150,14 +155,14 @@ mod rusqlite {
         let result = broken::migrations::new().run(&mut conn);
 
         assert!(result.is_err());
-        let current: u32 = conn
+        let current: String = conn
             .query_row(
-                "SELECT MAX(version) FROM keg_schema_history",
+                "SELECT MAX(version) FROM keg_schema_history WHERE success",
                 NO_PARAMS,
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(2, current);
+        assert_eq!("2", current);
     }
 
 
@@ -201,14 +206,14 @@ mod rusqlite {
 
         mod_migrations::migrations::new().run(&mut conn).unwrap();
 
-        let current: u32 = conn
+        let current: String = conn
             .query_row(
                 "SELECT MAX(version) FROM keg_schema_history",
                 NO_PARAMS,
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(3, current);
+        assert_eq!("3", current);
 
         let installed_on: DateTime<Local> = conn
             .query_row(
@@ -216,7 +221,8 @@ mod rusqlite {
                 NO_PARAMS,
                 |row| {
                     let _installed_on: String = row.get(0).unwrap();
-                    Ok(DateTime::parse_from_rfc3339(&_installed_on).unwrap().with_timezone(&Local))
+                    let _installed_on = NaiveDateTime::parse_from_str(&_installed_on, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+                    Ok(Utc.from_utc_datetime(&_installed_on).with_timezone(&Local))
                 }
             )
             .unwrap();
@@ -235,16 +241,897 @@ mod rusqlite {
         )
         .unwrap();
         let mchecksum = migration.checksum();
-        conn.migrate(&[migration]).unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
 
-        let (current, checksum): (u32, String) = conn
+        let (current, checksum): (String, String) = conn
             .query_row(
                 "SELECT version, checksum FROM keg_schema_history where version = (SELECT MAX(version) from keg_schema_history)",
                 NO_PARAMS,
                 |row| Ok((row.get(0).unwrap(), row.get(1).unwrap())),
             )
             .unwrap();
-        assert_eq!(4, current);
+        assert_eq!("4", current);
+        assert_eq!(mchecksum.to_string(), checksum);
+    }
+
+    #[test]
+    fn aborts_on_divergent_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename(
+            "V2__add_cars_table",
+            "CREATE TABLE cars (id int, name varchar(255), year int);",
+        )
+        .unwrap();
+        let result = conn.migrate(&[migration], false, None);
+
+        match result {
+            Err(Error::DivergentVersions(divergent)) => {
+                assert_eq!(1, divergent.len());
+                assert_eq!("V2__add_cars_table", divergent[0].1.to_string());
+            }
+            _ => panic!("expected divergent versions error"),
+        }
+    }
+
+    #[test]
+    fn upgrades_legacy_checksums() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        // checksum as stored by previous keg versions
+        let sql = include_str!("sql_migrations/V1__initial.sql");
+        let mut hasher = DefaultHasher::new();
+        "initial".hash(&mut hasher);
+        1usize.hash(&mut hasher);
+        sql.hash(&mut hasher);
+        conn.execute(
+            "UPDATE keg_schema_history SET checksum = ? WHERE version = 1",
+            &[&hasher.finish().to_string()],
+        )
+        .unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let checksum: String = conn
+            .query_row(
+                "SELECT checksum FROM keg_schema_history where version = 1",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        let migration = Migration::from_filename("V1__initial", sql).unwrap();
+        assert_eq!(migration.checksum().to_string(), checksum);
+    }
+
+    #[test]
+    fn applies_repeatable_migration_when_changed() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap()];
+        conn.migrate(&migrations, false, None).unwrap();
+        // unchanged repeatable migrations are not applied again
+        conn.migrate(&migrations, false, None).unwrap();
+
+        let migration = Migration::from_filename("R__cars_view", "CREATE VIEW cars_v2 AS SELECT 2").unwrap();
+        let mchecksum = migration.checksum();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        let (count, checksum): (u32, String) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(checksum) FROM keg_schema_history WHERE version IS NULL AND name = 'cars_view'",
+                NO_PARAMS,
+                |row| Ok((row.get(0).unwrap(), row.get(1).unwrap())),
+            )
+            .unwrap();
+        assert_eq!(1, count);
         assert_eq!(mchecksum.to_string(), checksum);
     }
+
+    #[test]
+    fn upgrades_schema_history_table_with_version_primary_key() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            NO_PARAMS,
+        )
+        .unwrap();
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM keg_schema_history", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(4, count);
+    }
+
+    #[test]
+    fn rolls_back_to_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+            Migration::from_filename("U2__add_cars_table", "DROP TABLE cars").unwrap(),
+        ];
+        let runner = Runner::new(&migrations);
+        runner.run(&mut conn).unwrap();
+        runner.rollback_to(&mut conn, 1.into()).unwrap();
+
+        let current: String = conn
+            .query_row(
+                "SELECT MAX(version) FROM keg_schema_history",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("1", current);
+
+        let tables: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='cars'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, tables);
+    }
+
+    #[test]
+    fn rolls_back_over_legacy_checksums() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let sql = include_str!("sql_migrations/V2__add_cars_table.sql");
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", sql).unwrap(),
+            Migration::from_filename("U2__add_cars_table", "DROP TABLE cars").unwrap(),
+        ];
+        let runner = Runner::new(&migrations);
+        runner.run(&mut conn).unwrap();
+
+        // checksum as stored by previous keg versions
+        let mut hasher = DefaultHasher::new();
+        "add_cars_table".hash(&mut hasher);
+        2usize.hash(&mut hasher);
+        sql.hash(&mut hasher);
+        conn.execute(
+            "UPDATE keg_schema_history SET checksum = ? WHERE version = 2",
+            &[&hasher.finish().to_string()],
+        )
+        .unwrap();
+
+        runner.rollback_to(&mut conn, 1.into()).unwrap();
+
+        let current: String = conn
+            .query_row(
+                "SELECT MAX(version) FROM keg_schema_history",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("1", current);
+    }
+
+    #[test]
+    fn rollback_aborts_on_missing_undo_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+        let result = embedded::migrations::new().rollback_to(&mut conn, 1.into());
+
+        match result {
+            Err(Error::MissingUndoMigration(_)) => (),
+            _ => panic!("expected missing undo migration error"),
+        }
+    }
+
+    #[test]
+    fn applies_out_of_order_migration_only_when_enabled() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V3__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        Runner::new(&migrations).run(&mut conn).unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_brand_to_cars_table", include_str!("sql_migrations/V3__add_brand_to_cars_table.sql")).unwrap(),
+            Migration::from_filename("V3__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        let mut runner = Runner::new(&migrations);
+        match runner.run(&mut conn) {
+            Err(Error::OutOfOrderMigrations(migrations)) => {
+                assert_eq!("V2__add_brand_to_cars_table", migrations[0].to_string());
+            }
+            _ => panic!("expected out of order migrations error"),
+        }
+
+        runner.set_out_of_order(true);
+        runner.run(&mut conn).unwrap();
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM keg_schema_history", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(3, count);
+    }
+
+    #[test]
+    fn applies_dotted_version_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1.2__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V1.10__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+        ];
+        Runner::new(&migrations).run(&mut conn).unwrap();
+
+        let versions: Vec<String> = conn
+            .prepare("SELECT version FROM keg_schema_history ORDER BY installed_on, name")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec!["1.2", "1.10"], versions);
+    }
+
+    #[test]
+    fn applies_timestamp_version_migration_on_legacy_schema_history_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            NO_PARAMS,
+        )
+        .unwrap();
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migration = Migration::from_filename(
+            "V20261017093000__add_year_to_cars_table",
+            "ALTER TABLE cars ADD year INTEGER;",
+        )
+        .unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        let version: String = conn
+            .query_row(
+                "SELECT version FROM keg_schema_history WHERE name = 'add_year_to_cars_table'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("20261017093000", version);
+    }
+
+    #[test]
+    fn fails_on_unparsable_applied_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+        conn.execute("UPDATE keg_schema_history SET version = 'one' WHERE version = '1'", NO_PARAMS)
+            .unwrap();
+
+        match embedded::migrations::new().run(&mut conn) {
+            Err(Error::TransactionError(_, _)) => (),
+            _ => panic!("expected the unparsable version to fail the run"),
+        }
+    }
+
+    #[test]
+    fn applies_migrations_above_baseline() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute_batch(include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap();
+
+        let runner = embedded::migrations::new();
+        runner.baseline(&mut conn, 2.into(), "existing schema").unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM keg_schema_history ORDER BY version")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec!["existing schema", "add_brand_to_cars_table"], names);
+    }
+
+    #[test]
+    fn baseline_aborts_on_existing_schema_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        match embedded::migrations::new().baseline(&mut conn, 2.into(), "existing schema") {
+            Err(Error::SchemaHistoryNotEmpty) => (),
+            _ => panic!("expected schema history not empty error"),
+        }
+    }
+
+    #[test]
+    fn migrates_to_target_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_target(2.into());
+        runner.run(&mut conn).unwrap();
+
+        let current: String = conn
+            .query_row(
+                "SELECT MAX(version) FROM keg_schema_history",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("2", current);
+
+        runner.set_target(1.into());
+        match runner.run(&mut conn) {
+            Err(Error::TargetVersionBelowCurrent(target, current)) => {
+                assert_eq!("1", target.to_string());
+                assert_eq!("2", current.to_string());
+            }
+            _ => panic!("expected target version below current error"),
+        }
+    }
+
+    #[test]
+    fn applies_repeatable_migrations_once_target_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+            Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT * FROM cars").unwrap(),
+        ];
+        let mut runner = Runner::new(&migrations);
+        runner.set_target(1.into());
+        let report = runner.run(&mut conn).unwrap();
+        assert_eq!(1, report.applied().len());
+
+        runner.set_target(2.into());
+        let report = runner.run(&mut conn).unwrap();
+        let applied = report.applied().iter().map(|m| m.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["add_cars_table", "cars_view"], applied);
+    }
+
+    #[test]
+    fn plans_pending_migrations_without_applying_them() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_target(2.into());
+        runner.run(&mut conn).unwrap();
+
+        let plan = embedded::migrations::new().plan(&mut conn).unwrap();
+        let pending = plan.pending().iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(vec!["V3__add_brand_to_cars_table"], pending);
+        assert!(plan.problems().is_empty());
+
+        let migration = Migration::from_filename(
+            "V2__add_cars_table",
+            "CREATE TABLE cars (id int);",
+        )
+        .unwrap();
+        let plan = Runner::new(&[migration]).plan(&mut conn).unwrap();
+        assert!(plan.pending().is_empty());
+        match plan.problems() {
+            [Error::DivergentVersions(divergent)] => assert_eq!(1, divergent.len()),
+            _ => panic!("expected divergent versions problem"),
+        }
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM keg_schema_history", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn plans_and_lists_without_creating_schema_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let runner = embedded::migrations::new();
+        let plan = runner.plan(&mut conn).unwrap();
+        assert_eq!(3, plan.pending().len());
+        assert!(plan.problems().is_empty());
+        let info = runner.info(&mut conn).unwrap();
+        assert!(info.iter().all(|i| i.state() == MigrationState::Pending));
+
+        let tables: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='keg_schema_history'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, tables);
+    }
+
+    #[test]
+    fn reports_applied_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report
+            .applied()
+            .iter()
+            .map(|m| (m.version().unwrap().to_string(), m.name().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("1".to_string(), "initial".to_string()),
+                ("2".to_string(), "add_cars_table".to_string()),
+                ("3".to_string(), "add_brand_to_cars_table".to_string()),
+            ],
+            applied
+        );
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        assert!(report.applied().is_empty());
+    }
+
+    #[test]
+    fn reports_migrations_applied_before_failure() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        match broken::migrations::new().run(&mut conn) {
+            Err(Error::RunAborted(_, report)) => assert_eq!(2, report.applied().len()),
+            _ => panic!("expected run aborted error"),
+        }
+    }
+
+    #[test]
+    fn lists_migrations_info() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", "CREATE TABLE cars (id int);").unwrap(),
+            Migration::from_filename("V4__add_year_to_cars_table", "ALTER TABLE cars ADD year INTEGER;").unwrap(),
+            Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap(),
+        ];
+        let info = Runner::new(&migrations).info(&mut conn).unwrap();
+        let states = info.iter().map(|i| i.state()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                MigrationState::Applied,
+                MigrationState::ChecksumMismatch,
+                MigrationState::MissingLocally,
+                MigrationState::Pending,
+                MigrationState::Pending,
+            ],
+            states
+        );
+        assert_eq!("add_brand_to_cars_table", info[2].applied().unwrap().name());
+    }
+
+    #[test]
+    fn reruns_failed_migration_rolled_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        broken::migrations::new().run(&mut conn).unwrap_err();
+        let failed: u32 = conn
+            .query_row("SELECT COUNT(*) FROM keg_schema_history WHERE NOT success", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(0, failed);
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let current: String = conn
+            .query_row(
+                "SELECT MAX(version) FROM keg_schema_history",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("3", current);
+    }
+
+    #[test]
+    fn aborts_on_failed_migration_until_repaired() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let broken = vec![Migration::from_filename(
+            "V1__add_notes",
+            "-- keg:no-transaction\nCREATE TABLE notes(id int);\nINSERT INTO missing VALUES (1);",
+        )
+        .unwrap()];
+        Runner::new(&broken).run(&mut conn).unwrap_err();
+
+        // fixing the sql changes the checksum, the failed attempt is still the one reported
+        let fixed = vec![Migration::from_filename(
+            "V1__add_notes",
+            "-- keg:no-transaction\nCREATE TABLE IF NOT EXISTS notes(id int);\nINSERT INTO notes VALUES (1);",
+        )
+        .unwrap()];
+        let runner = Runner::new(&fixed);
+        match runner.run(&mut conn) {
+            Err(Error::FailedMigrations(failed)) => {
+                assert_eq!("V1__add_notes", failed[0].to_string());
+            }
+            _ => panic!("expected failed migrations error"),
+        }
+
+        runner.repair(&mut conn).unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM notes", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn waits_for_migrations_lock() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // the lock is a row in the database, so the same connection can't take it twice
+        assert!(conn.lock(Duration::from_secs(1)).unwrap());
+
+        let mut runner = embedded::migrations::new();
+        runner.set_lock_timeout(Duration::from_millis(200));
+        match runner.run(&mut conn) {
+            Err(Error::LockTimeout(_)) => (),
+            _ => panic!("expected lock timeout error"),
+        }
+
+        conn.unlock().unwrap();
+        runner.run(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn takes_over_stale_migrations_lock() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(conn.lock(Duration::from_secs(1)).unwrap());
+        // a run that crashed holding the lock a day ago
+        conn.execute(
+            "UPDATE keg_schema_history_lock SET locked_on = datetime('now', '-1 day')",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_lock_timeout(Duration::from_millis(200));
+        match runner.run(&mut conn) {
+            Err(Error::LockTimeout(_)) => (),
+            _ => panic!("expected lock timeout error"),
+        }
+
+        runner.set_stale_lock_timeout(Duration::from_secs(60 * 60));
+        runner.run(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn keeps_live_migrations_lock() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // a run holding the lock for a minute already
+        assert!(conn.lock(Duration::from_secs(1)).unwrap());
+        conn.execute(
+            "UPDATE keg_schema_history_lock SET locked_on = datetime('now', '-1 minute')",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let mut runner = embedded::migrations::new();
+        runner.set_lock_timeout(Duration::from_millis(200));
+        runner.set_stale_lock_timeout(Duration::from_secs(60 * 60));
+        match runner.run(&mut conn) {
+            Err(Error::LockTimeout(_)) => (),
+            _ => panic!("expected lock timeout error"),
+        }
+    }
+
+    #[test]
+    fn applies_multi_statement_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_cars_and_motos",
+            "CREATE TABLE cars(id int);\nCREATE TABLE motos(id int);\nINSERT INTO motos VALUES (1);",
+        )
+        .unwrap();
+        let report = conn.migrate(&[migration], false, None).unwrap();
+        assert_eq!(1, report.applied()[0].rows_affected());
+
+        let motos: i64 = conn
+            .query_row("SELECT COUNT(*) FROM motos", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(1, motos);
+    }
+
+    #[test]
+    fn reports_failed_statement() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_cars",
+            "CREATE TABLE cars(id int);\nINSERT INTO motos VALUES (1);",
+        )
+        .unwrap();
+        let err = conn.migrate(&[migration], false, None).unwrap_err();
+        assert!(err.to_string().contains("statement 2: INSERT INTO motos VALUES (1)"));
+    }
+
+    #[test]
+    fn applies_trigger_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename(
+            "V1__add_cars_trigger",
+            "CREATE TABLE cars(id int, name varchar(255));\n\
+             CREATE TABLE cars_log(id int);\n\
+             CREATE TRIGGER log_cars AFTER INSERT ON cars BEGIN\n\
+               INSERT INTO cars_log VALUES (new.id);\n\
+             END;\n\
+             INSERT INTO cars VALUES (1, 'a;b');",
+        )
+        .unwrap();
+        conn.migrate(&[migration], false, None).unwrap();
+
+        let logged: i64 = conn
+            .query_row("SELECT COUNT(*) FROM cars_log", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(1, logged);
+    }
+
+    #[test]
+    fn applies_no_transaction_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = vec![
+            Migration::from_filename("V1__add_cars", "CREATE TABLE cars(id int);").unwrap(),
+            Migration::from_filename("V2__vacuum", "-- keg:no-transaction\nVACUUM;").unwrap(),
+        ];
+        let report = Runner::new(&migrations).run(&mut conn).unwrap();
+        assert_eq!(2, report.applied().len());
+    }
+
+    #[test]
+    fn refuses_no_transaction_migration_when_grouped() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = vec![
+            Migration::from_filename("V1__add_cars", "CREATE TABLE cars(id int);").unwrap(),
+            Migration::from_filename("V2__vacuum", "-- keg:no-transaction\nVACUUM;").unwrap(),
+        ];
+        let mut runner = Runner::new(&migrations);
+        runner.set_grouped(true);
+        match runner.plan(&mut conn).unwrap().problems() {
+            [Error::NoTransactionMigrationsGrouped(migrations)] => assert_eq!(1, migrations.len()),
+            _ => panic!("expected no-transaction migrations problem"),
+        }
+        match runner.run(&mut conn) {
+            Err(Error::NoTransactionMigrationsGrouped(migrations)) => {
+                assert_eq!(1, migrations.len());
+                assert_eq!("vacuum", migrations[0].name());
+            }
+            _ => panic!("test failed"),
+        }
+    }
+
+    #[test]
+    fn records_baseline_description_verbatim() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let description = "schema of the 'legacy' app; is it? -- yes";
+        Runner::new(&[]).baseline(&mut conn, 2.into(), description).unwrap();
+
+        let name: String = conn
+            .query_row("SELECT name FROM keg_schema_history", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(description, name);
+    }
+
+    #[test]
+    fn records_migration_details_in_schema_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        embedded::migrations::new().run(&mut conn).unwrap();
+
+        let (description, migration_type, installed_by, execution_time_ms): (Option<String>, String, Option<String>, Option<i64>) = conn
+            .query_row(
+                "SELECT description, type, installed_by, execution_time_ms FROM keg_schema_history WHERE version = '2'",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        // the migration has no keg:description directive
+        assert_eq!(None, description);
+        assert_eq!("SQL", migration_type);
+        // SQLite has no users
+        assert_eq!(None, installed_by);
+        assert!(execution_time_ms.unwrap() >= 0);
+    }
+
+    #[test]
+    fn reads_back_migration_types_and_descriptions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migrations = [
+            Migration::from_filename("V2__add_cars_table", "-- keg:description cars of the persons\nCREATE TABLE cars (id int);")
+                .unwrap(),
+            Migration::from_filename("R__cars_view", "CREATE VIEW cars_v1 AS SELECT 1").unwrap(),
+        ];
+        let runner = Runner::new(&migrations);
+        runner.baseline(&mut conn, 1.into(), "existing schema").unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let info = runner.info(&mut conn).unwrap();
+        let applied = info.iter().map(|i| i.applied().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![AppliedMigrationType::Baseline, AppliedMigrationType::Sql, AppliedMigrationType::RepeatableSql],
+            applied.iter().map(|a| a.migration_type()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![Some("existing schema"), Some("cars of the persons"), None],
+            applied.iter().map(|a| a.description()).collect::<Vec<_>>()
+        );
+        assert_eq!(None, applied[2].version());
+        assert!(applied.iter().all(|a| a.installed_on().is_some()));
+    }
+
+    #[test]
+    fn records_baseline_and_mod_migration_types() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let runner = mod_migrations::migrations::new();
+        runner.baseline(&mut conn, 1.into(), "existing schema").unwrap();
+        runner.run(&mut conn).unwrap();
+
+        let types: Vec<(String, String)> = conn
+            .prepare("SELECT version, type FROM keg_schema_history ORDER BY version")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            vec![
+                ("1".to_string(), "BASELINE".to_string()),
+                ("2".to_string(), "RUST".to_string()),
+                ("3".to_string(), "RUST".to_string()),
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn upgrades_legacy_schema_history_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute_batch(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES (1, 'initial', '2026-10-17T11:30:00.250+02:00', ?)",
+            &[migration.checksum().to_string()],
+        )
+        .unwrap();
+        let report = conn.migrate(&[migration], false, None).unwrap();
+        assert!(report.applied().is_empty());
+
+        let (version, installed_on, migration_type): (String, String, Option<String>) = conn
+            .query_row("SELECT version, installed_on, type FROM keg_schema_history", NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!("1", version);
+        // converted to UTC
+        assert_eq!("2026-10-17 09:30:00.250", installed_on);
+        assert_eq!(None, migration_type);
+    }
+
+    #[test]
+    fn finishes_upgrade_interrupted_after_dropping_schema_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute_batch(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history_upgrade(version VARCHAR(50) UNIQUE, name VARCHAR(255), \
+             description VARCHAR(255), type VARCHAR(20), installed_on DATETIME, installed_by VARCHAR(100), \
+             execution_time_ms INTEGER, checksum VARCHAR(255), success BOOLEAN)",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history_upgrade (version, name, installed_on, checksum) VALUES ('1', 'initial', '2026-10-17 09:30:00.000', ?)",
+            &[migration.checksum().to_string()],
+        )
+        .unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report.applied().iter().map(|m| m.version().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["2", "3"], applied);
+
+        let upgrade_tables: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='keg_schema_history_upgrade'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, upgrade_tables);
+    }
+
+    #[test]
+    fn restarts_upgrade_interrupted_before_dropping_schema_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let migration = Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute_batch(include_str!("sql_migrations/V1__initial.sql")).unwrap();
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history (version, name, installed_on, checksum) VALUES (1, 'initial', '2026-10-17T11:30:00+02:00', ?)",
+            &[migration.checksum().to_string()],
+        )
+        .unwrap();
+        // the upgrade table already holds the copied row
+        conn.execute(
+            "CREATE TABLE keg_schema_history_upgrade(version VARCHAR(50) UNIQUE, name VARCHAR(255), \
+             description VARCHAR(255), type VARCHAR(20), installed_on DATETIME, installed_by VARCHAR(100), \
+             execution_time_ms INTEGER, checksum VARCHAR(255), success BOOLEAN)",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO keg_schema_history_upgrade (version, name) VALUES ('1', 'initial')",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let report = embedded::migrations::new().run(&mut conn).unwrap();
+        let applied = report.applied().iter().map(|m| m.version().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["2", "3"], applied);
+
+        let installed_on: String = conn
+            .query_row("SELECT installed_on FROM keg_schema_history WHERE version = '1'", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!("2026-10-17 09:30:00.000", installed_on);
+    }
+
+    #[test]
+    fn plans_without_upgrading_legacy_schema_history_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        conn.execute(
+            "CREATE TABLE keg_schema_history(version INTEGER PRIMARY KEY, name VARCHAR(255), installed_on VARCHAR(255), checksum VARCHAR(255))",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let plan = embedded::migrations::new().plan(&mut conn).unwrap();
+        assert!(plan.pending().is_empty());
+        match plan.problems() {
+            [Error::SchemaHistoryNeedsUpgrade] => (),
+            _ => panic!("expected schema history needs upgrade problem"),
+        }
+        match embedded::migrations::new().info(&mut conn) {
+            Err(Error::SchemaHistoryNeedsUpgrade) => (),
+            _ => panic!("expected schema history needs upgrade error"),
+        }
+
+        // the new columns would have been added by an upgrade
+        assert!(conn.prepare("SELECT type FROM keg_schema_history").is_err());
+    }
 }
@@ -0,0 +1,164 @@
+#![cfg(feature = "ttokio_postgres")]
+
+mod tokio_postgres {
+    use keg::{AsyncLock as _, Error, Migration, MigrationState, Runner};
+    use std::time::Duration;
+    use ttokio_postgres::{Client, NoTls};
+
+    mod embedded {
+        use keg::embed_migrations;
+        embed_migrations!("tests/sql_migrations");
+    }
+
+    mod broken {
+        use keg::embed_migrations;
+        embed_migrations!("tests/sql_migrations_broken");
+    }
+
+    async fn connect(url: &str) -> Client {
+        let (client, connection) = ttokio_postgres::connect(url, NoTls).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    async fn clean_database() {
+        let client = connect("postgres://postgres@localhost:5432/template1").await;
+
+        client
+            .execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname='postgres'",
+                &[],
+            )
+            .await
+            .unwrap();
+        client.execute("DROP DATABASE postgres", &[]).await.unwrap();
+        client.execute("CREATE DATABASE POSTGRES", &[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn embedded_applies_migration() {
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+        embedded::migrations::new().run_async(&mut client).await.unwrap();
+
+        client
+            .execute(
+                "INSERT INTO persons (name, city) VALUES ($1, $2)",
+                &[&"John Legend", &"New York"],
+            )
+            .await
+            .unwrap();
+        for row in client.query("SELECT name, city FROM persons", &[]).await.unwrap() {
+            let name: String = row.get(0);
+            let city: String = row.get(1);
+            assert_eq!("John Legend", name);
+            assert_eq!("New York", city);
+        }
+        clean_database().await;
+    }
+
+    #[tokio::test]
+    async fn embedded_updates_schema_history_grouped() {
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+        let mut runner = embedded::migrations::new();
+        runner.set_grouped(true);
+        let report = runner.run_async(&mut client).await.unwrap();
+        assert_eq!(3, report.applied().len());
+
+        for row in client
+            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .await
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("3", current);
+        }
+        clean_database().await;
+    }
+
+    #[tokio::test]
+    async fn reports_migrations_applied_before_failure() {
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+
+        match broken::migrations::new().run_async(&mut client).await {
+            Err(Error::RunAborted(_, report)) => assert_eq!(2, report.applied().len()),
+            _ => panic!("expected run aborted error"),
+        }
+
+        for row in client
+            .query("SELECT MAX(version) FROM keg_schema_history WHERE success", &[])
+            .await
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("2", current);
+        }
+        clean_database().await;
+    }
+
+    #[tokio::test]
+    async fn waits_for_migrations_lock() {
+        let mut holder = connect("postgres://postgres@localhost:5432/postgres").await;
+        assert!(holder.lock(Duration::from_secs(1)).await.unwrap());
+
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+        let mut runner = embedded::migrations::new();
+        runner.set_lock_timeout(Duration::from_millis(200));
+        match runner.run_async(&mut client).await {
+            Err(Error::LockTimeout(_)) => (),
+            _ => panic!("expected lock timeout error"),
+        }
+
+        holder.unlock().await.unwrap();
+        runner.run_async(&mut client).await.unwrap();
+        clean_database().await;
+    }
+
+    #[tokio::test]
+    async fn plans_and_lists_without_creating_schema_history() {
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+        let runner = embedded::migrations::new();
+        let plan = runner.plan_async(&mut client).await.unwrap();
+        assert_eq!(3, plan.pending().len());
+        assert!(plan.problems().is_empty());
+        let info = runner.info_async(&mut client).await.unwrap();
+        assert!(info.iter().all(|i| i.state() == MigrationState::Pending));
+
+        for row in client
+            .query("SELECT COUNT(*) FROM information_schema.tables WHERE table_name='keg_schema_history'", &[])
+            .await
+            .unwrap()
+        {
+            let count: i64 = row.get(0);
+            assert_eq!(0, count);
+        }
+        clean_database().await;
+    }
+
+    #[tokio::test]
+    async fn baselines_and_rolls_back() {
+        let mut client = connect("postgres://postgres@localhost:5432/postgres").await;
+        client.batch_execute(include_str!("sql_migrations/V1__initial.sql")).await.unwrap();
+
+        let migrations = [
+            Migration::from_filename("V1__initial", include_str!("sql_migrations/V1__initial.sql")).unwrap(),
+            Migration::from_filename("V2__add_cars_table", include_str!("sql_migrations/V2__add_cars_table.sql")).unwrap(),
+            Migration::from_filename("U2__add_cars_table", "DROP TABLE cars").unwrap(),
+        ];
+        let runner = Runner::new(&migrations);
+        runner.baseline_async(&mut client, 1.into(), "existing schema").await.unwrap();
+        let report = runner.run_async(&mut client).await.unwrap();
+        assert_eq!(1, report.applied().len());
+
+        runner.rollback_to_async(&mut client, 1.into()).await.unwrap();
+        runner.repair_async(&mut client).await.unwrap();
+        for row in client
+            .query("SELECT MAX(version) FROM keg_schema_history", &[])
+            .await
+            .unwrap()
+        {
+            let current: String = row.get(0);
+            assert_eq!("1", current);
+        }
+        clean_database().await;
+    }
+}